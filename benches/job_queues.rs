@@ -0,0 +1,44 @@
+//! Compares the per-worker job queues with a single shared queue on many tiny files, where the
+//! workers spend most of their time taking jobs rather than compressing.
+//!
+//! Run with `cargo bench --bench job_queues`. Keeping the order makes all workers take their jobs
+//! from the front of one queue, so it stands in for the single shared queue.
+
+use std::time::{Duration, Instant};
+
+use mtzip::{CompressionType, ZipArchive};
+
+const FILES: usize = 200_000;
+const RUNS: usize = 5;
+
+/// Fastest of several runs of compressing [`FILES`] tiny files
+fn bench(threads: usize, single_queue: bool) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let mut zipper = ZipArchive::new();
+            zipper.set_preserve_order(single_queue);
+            for i in 0..FILES {
+                zipper
+                    .add_file_from_memory(b"tiny".as_slice(), format!("{i}.txt"))
+                    .compression_type(CompressionType::Stored)
+                    .done();
+            }
+            let start = Instant::now();
+            zipper.compress_with_threads(threads).unwrap();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let cores = std::thread::available_parallelism().map_or(1, usize::from);
+    // More threads than cores make the contention visible on small machines too
+    for threads in [cores, cores * 4] {
+        let single = bench(threads, true);
+        let per_worker = bench(threads, false);
+        println!("{FILES} tiny files, {threads} threads");
+        println!("  single queue:      {single:?}");
+        println!("  per-worker queues: {per_worker:?}");
+    }
+}
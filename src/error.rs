@@ -0,0 +1,134 @@
+use core::fmt::Display;
+use std::path::PathBuf;
+
+use crate::CompressionType;
+
+/// Error that can occur while compressing or writing an archive.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// I/O error while reading the input data or writing the archive
+    Io(std::io::Error),
+    /// An entry is larger than the archive format allows. Streamed entries of unknown length only
+    /// have room for 32-bit sizes in their headers, so they can't reach 4 GiB.
+    EntryTooLarge,
+    /// The archive is larger than the archive format allows
+    ArchiveTooLarge,
+    /// A path in the archive is longer than 65535 bytes
+    FilenameTooLong(String),
+    /// Extra fields of the entry at this path are longer than 65535 bytes in total, including the
+    /// ones added for ZIP64 and alignment
+    ExtraFieldTooLong(String),
+    /// Multiple entries have the same path in the archive
+    DuplicatePath(String),
+    /// A file or archive comment is longer than 65535 bytes
+    CommentTooLong,
+    /// The operation was cancelled
+    Cancelled,
+    /// A path in the archive has a `..` component, which would point outside of the extraction
+    /// directory, or the path of a file ends with a slash
+    InvalidPath(String),
+    /// A path in the archive is used both for a file and a directory
+    PathConflict(String),
+    /// Gzip output requires the archive to contain exactly one file that is not encrypted
+    NotSingleFile,
+    /// Volumes of a split archive must be at least 64 KiB
+    VolumeTooSmall,
+    /// A filesystem path has no file name, or the file name is not valid UTF-8, so it can't be
+    /// used as a path in the archive
+    InvalidFileName(PathBuf),
+    /// Files can't be compressed with this compression type, only
+    /// [pre-compressed](crate::ZipArchive::add_precompressed) data can use it
+    UnsupportedCompression(CompressionType),
+    /// The CRC-32 of an entry doesn't match its data, only checked if
+    /// [enabled](crate::ZipArchive::set_verify_crc)
+    CrcMismatch(String),
+    /// A file added from the filesystem couldn't be opened when it was going to be compressed,
+    /// for example because it was deleted in the meantime
+    OpenInput {
+        /// Path of the file in the archive
+        archive_path: String,
+        /// Path of the file in the filesystem
+        fs_path: PathBuf,
+        /// Error that occurred while opening the file
+        source: std::io::Error,
+    },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {e}"),
+            Self::EntryTooLarge => write!(f, "Entry is too large"),
+            Self::ArchiveTooLarge => write!(f, "Archive is too large"),
+            Self::FilenameTooLong(path) => {
+                write!(f, "Path in the archive is longer than 65535 bytes: {path}")
+            }
+            Self::ExtraFieldTooLong(path) => {
+                write!(f, "Extra fields are longer than 65535 bytes: {path}")
+            }
+            Self::DuplicatePath(path) => write!(f, "Duplicate path in the archive: {path}"),
+            Self::CommentTooLong => write!(f, "Comment is longer than 65535 bytes"),
+            Self::Cancelled => write!(f, "Operation was cancelled"),
+            Self::InvalidPath(path) => write!(f, "Invalid path in the archive: {path}"),
+            Self::PathConflict(path) => {
+                write!(f, "Path is used for both a file and a directory: {path}")
+            }
+            Self::NotSingleFile => {
+                write!(
+                    f,
+                    "Archive must contain exactly one unencrypted file for gzip output"
+                )
+            }
+            Self::VolumeTooSmall => write!(f, "Volume size must be at least 64 KiB"),
+            Self::InvalidFileName(path) => {
+                write!(f, "Path has no valid UTF-8 file name: {}", path.display())
+            }
+            Self::UnsupportedCompression(compression_type) => {
+                write!(f, "Compressing with {compression_type:?} is not supported")
+            }
+            Self::CrcMismatch(path) => write!(f, "CRC-32 doesn't match the data of {path}"),
+            Self::OpenInput {
+                archive_path,
+                fs_path,
+                source,
+            } => write!(
+                f,
+                "Failed to open {} for {archive_path}: {source}",
+                fs_path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::OpenInput { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    /// Errors of this crate that were wrapped in an I/O error are unwrapped
+    fn from(value: std::io::Error) -> Self {
+        if value.get_ref().is_some_and(|inner| inner.is::<Self>()) {
+            // Checked above
+            return *value.into_inner().unwrap().downcast::<Self>().unwrap();
+        }
+        Self::Io(value)
+    }
+}
+
+impl From<Error> for std::io::Error {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::Io(e) => e,
+            Error::Cancelled => Self::new(std::io::ErrorKind::Interrupted, value),
+            Error::OpenInput { ref source, .. } => Self::new(source.kind(), value),
+            _ => Self::new(std::io::ErrorKind::InvalidInput, value),
+        }
+    }
+}
@@ -8,11 +8,31 @@ use flate2::Compression;
 /// Compression level that should be used when compressing a file or data.
 ///
 /// Current compression providers support only levels from 0 to 9, so these are the only ones being
-/// supported.
+/// supported. [`none`](Self::none) is 0, [`fast`](Self::fast) is 1, [`balanced`](Self::balanced)
+/// is 6 and [`best`](Self::best) is 9.
+///
+/// ```
+/// # use mtzip::level::CompressionLevel;
+/// // Level from a `-0`..`-9` command line flag
+/// let flag = "-3";
+/// let level = flag
+///     .strip_prefix('-')
+///     .and_then(|level| level.parse().ok())
+///     .and_then(CompressionLevel::new)
+///     .unwrap_or_default();
+/// assert_eq!(level.as_u8(), 3);
+/// assert!((CompressionLevel::MIN..=CompressionLevel::MAX).contains(&level));
+/// ```
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct CompressionLevel(u8);
 
 impl CompressionLevel {
+    /// Lowest supported level, same as [`none`](Self::none)
+    pub const MIN: Self = Self::none();
+
+    /// Highest supported level, same as [`best`](Self::best)
+    pub const MAX: Self = Self::best();
+
     /// Construct a new value of a compression level setting.
     ///
     /// The integer value must be less than or equal to 9, otherwise `None` is returned
@@ -64,6 +84,12 @@ impl CompressionLevel {
     pub const fn get(self) -> u8 {
         self.0
     }
+
+    /// Get the compression level as an integer. Same as [`get`](Self::get)
+    #[inline]
+    pub const fn as_u8(self) -> u8 {
+        self.0
+    }
 }
 
 impl Default for CompressionLevel {
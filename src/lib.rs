@@ -42,27 +42,42 @@
 
 use std::{
     borrow::Cow,
-    io::{Read, Seek, Write},
+    collections::{HashMap, HashSet},
+    io::{Read, Seek, SeekFrom, Write},
     num::NonZeroUsize,
     panic::{RefUnwindSafe, UnwindSafe},
-    path::Path,
-    sync::{mpsc, Mutex},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    time::SystemTime,
 };
 
+use cfg_if::cfg_if;
+use derivative::Derivative;
 use level::CompressionLevel;
 #[cfg(feature = "rayon")]
 use rayon::prelude::*;
+#[cfg(feature = "rayon")]
+use std::sync::Mutex;
 use zip_archive_parts::{
     data::ZipData,
-    extra_field::{ExtraField, ExtraFields},
-    file::ZipFile,
-    job::{ZipJob, ZipJobOrigin},
+    file::DEFAULT_UNIX_SYMLINK_ATTRS,
+    gzip::write_gzip_member,
+    job::{system_time_to_secs, TextFlag, ZipJob, ZipJobOrigin},
+    limit::{BufferLimit, LimitedReceiver},
+    queue::JobQueues,
+    stream::PositionTrackingWriter,
 };
 
+mod error;
 pub mod level;
 mod zip_archive_parts;
 
-pub use zip_archive_parts::extra_field;
+pub use error::Error;
+pub use zip_archive_parts::extra_field::{self, ExtraField, ExtraFields};
+pub use zip_archive_parts::file::{ZipFile, ZipFileHeader};
 
 // TODO: tests, maybe examples
 
@@ -78,6 +93,341 @@ pub enum CompressionType {
     #[default]
     /// Deflate compression, the most common in ZIP files.
     Deflate = 8,
+    /// Deflate64, also known as Enhanced Deflate, a variant of deflate with a 64 KiB window that
+    /// some Windows tools produce.
+    ///
+    /// There is no Deflate64 compressor, so compressing files with it fails with
+    /// [`Error::UnsupportedCompression`]. Data compressed with it elsewhere can be added with
+    /// [`add_precompressed`](ZipArchive::add_precompressed) and is written with the headers
+    /// marking it accordingly.
+    Deflate64 = 9,
+    /// Not a compression method, the type is chosen by the selector set with
+    /// [`set_compression_selector`](ZipArchive::set_compression_selector) when the file is
+    /// compressed. Files added while a selector is set use this unless another type is set for
+    /// them. Without a selector it stands for the type set with
+    /// [`set_default_compression`](ZipArchive::set_default_compression).
+    ///
+    /// It is never written to headers, adding [precompressed](ZipArchive::add_precompressed) data
+    /// with it fails with [`Error::UnsupportedCompression`].
+    Default = u16::MAX,
+}
+
+impl CompressionType {
+    /// Get the compression type from the compression method number used in the headers. Returns
+    /// `None` for methods that are not supported.
+    ///
+    /// ```
+    /// # use mtzip::CompressionType;
+    /// assert_eq!(CompressionType::try_from_u16(8), Some(CompressionType::Deflate));
+    /// assert_eq!(CompressionType::try_from_u16(12), None);
+    /// ```
+    #[inline]
+    pub const fn try_from_u16(method: u16) -> Option<Self> {
+        match method {
+            0 => Some(Self::Stored),
+            8 => Some(Self::Deflate),
+            9 => Some(Self::Deflate64),
+            _ => None,
+        }
+    }
+
+    /// Lowercase name of the compression type, same as its [`Display`](std::fmt::Display)
+    /// output.
+    ///
+    /// ```
+    /// # use mtzip::CompressionType;
+    /// // Method from a `--method stored|deflate` command line option
+    /// let method = "stored";
+    /// let compression_type = [CompressionType::Stored, CompressionType::Deflate]
+    ///     .into_iter()
+    ///     .find(|compression_type| compression_type.as_str() == method);
+    /// assert_eq!(compression_type, Some(CompressionType::Stored));
+    /// ```
+    #[inline]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Stored => "stored",
+            Self::Deflate => "deflate",
+            Self::Deflate64 => "deflate64",
+            Self::Default => "default",
+        }
+    }
+}
+
+impl std::fmt::Display for CompressionType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Strategy of the [deflate](CompressionType::Deflate) compressor, for tuning it to the kind of
+/// data being compressed. Default is [`Default`](DeflateStrategy::Default).
+///
+/// flate2 doesn't allow to choose the strategy, so the other strategies use miniz_oxide directly.
+/// They are only available with the `rust_backend` feature, which is enabled by default, and are
+/// treated as [`Default`](DeflateStrategy::Default) without it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DeflateStrategy {
+    /// Suitable for most data.
+    #[default]
+    Default = 0,
+    /// Only use matches that are at least 5 bytes long, for data that consists mostly of small
+    /// values with a somewhat random distribution.
+    Filtered = 1,
+    /// Don't look for matches and only Huffman-encode the data, for data that has few repeating
+    /// sequences, such as random or already compressed data.
+    HuffmanOnly = 2,
+    /// Only look for runs of the same byte, for data such as images with large areas of a single
+    /// color.
+    Rle = 3,
+    /// Only use the fixed Huffman codes from the specification, for very small files.
+    Fixed = 4,
+}
+
+/// What to do with symbolic links when adding files from the filesystem, see
+/// [`ZipArchive::set_symlink_policy`]. Default is [`Follow`](SymlinkPolicy::Follow).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Archive the data of the file the link points to
+    #[default]
+    Follow,
+    /// Archive the link itself, as with [`ZipArchive::add_symlink`]
+    Preserve,
+}
+
+/// Operating system the archive is made on, stored in the "version made by" field of each entry.
+/// It determines how extractors interpret the
+/// [external attributes](ZipFileBuilder::external_attributes).
+///
+/// Default is the OS this crate is compiled for.
+#[repr(u8)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum HostOs {
+    /// UNIX, id 3. External attributes hold the [`UnixMode`] in the high 16 bits.
+    #[cfg_attr(not(target_os = "windows"), default)]
+    Unix = 3,
+    /// Windows NTFS, id 11 per Info-Zip spec. External attributes hold MS-DOS attributes in the
+    /// low 16 bits.
+    #[cfg_attr(target_os = "windows", default)]
+    Windows = 11,
+    /// macOS, id 19. External attributes are the same as on [UNIX](Self::Unix).
+    MacOs = 19,
+}
+
+impl HostOs {
+    /// Get the host OS from its id, the high byte of the "version made by" field. Returns `None`
+    /// for the ones that are not supported.
+    ///
+    /// ```
+    /// # use mtzip::HostOs;
+    /// assert_eq!(HostOs::try_from_u8(3), Some(HostOs::Unix));
+    /// assert_eq!(HostOs::try_from_u8(0), None);
+    /// ```
+    #[inline]
+    pub const fn try_from_u8(id: u8) -> Option<Self> {
+        match id {
+            3 => Some(Self::Unix),
+            11 => Some(Self::Windows),
+            19 => Some(Self::MacOs),
+            _ => None,
+        }
+    }
+}
+
+/// UNIX file mode: file type and permission bits, as in `st_mode`.
+///
+/// It is stored in the high 16 bits of the external file attributes, the low 16 bits are used for
+/// MS-DOS attributes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnixMode(pub u16);
+
+impl UnixMode {
+    /// Mask of the file type bits
+    pub const FILE_TYPE_MASK: u16 = 0o170000;
+    /// Regular file type
+    pub const REGULAR_FILE: u16 = 0o100000;
+    /// Directory file type
+    pub const DIRECTORY: u16 = 0o040000;
+    /// Symbolic link file type
+    pub const SYMLINK: u16 = 0o120000;
+
+    /// Get the raw mode value
+    #[inline]
+    pub const fn get(self) -> u16 {
+        self.0
+    }
+
+    /// Whether file type bits are set
+    #[inline]
+    pub const fn has_file_type(self) -> bool {
+        self.0 & Self::FILE_TYPE_MASK != 0
+    }
+}
+
+impl From<u16> for UnixMode {
+    #[inline]
+    fn from(value: u16) -> Self {
+        Self(value)
+    }
+}
+
+impl From<UnixMode> for u16 {
+    #[inline]
+    fn from(value: UnixMode) -> Self {
+        value.0
+    }
+}
+
+/// Set of per-entry options that can be reused for multiple entries, applied with
+/// [`ZipFileBuilder::options`]. Each option that is not set leaves the builder's value as-is.
+///
+/// ```
+/// # use mtzip::{level::CompressionLevel, CompressionType, FileOptions, ZipArchive};
+/// let options = FileOptions::new()
+///     .compression(CompressionType::Deflate)
+///     .level(CompressionLevel::fast())
+///     .unix_mode(0o644);
+///
+/// let mut zipper = ZipArchive::new();
+/// zipper.add_file_from_memory_with(b"first".as_slice(), "first.txt".to_owned(), &options);
+/// zipper.add_file_from_memory_with(b"second".as_slice(), "second.txt".to_owned(), &options);
+/// ```
+#[must_use]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileOptions {
+    compression_type: Option<CompressionType>,
+    compression_level: Option<CompressionLevel>,
+    unix_mode: Option<UnixMode>,
+    extra_fields: Vec<ExtraField>,
+    file_comment: Option<String>,
+    modification_time: Option<SystemTime>,
+    known_crc: Option<u32>,
+    executable: Option<bool>,
+    dos_attributes: Option<u16>,
+}
+
+impl FileOptions {
+    /// Create an empty set of options
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set compression type, see [`ZipFileBuilder::compression_type`].
+    pub fn compression(mut self, compression_type: CompressionType) -> Self {
+        self.compression_type = Some(compression_type);
+        self
+    }
+
+    /// Set compression level, see [`ZipFileBuilder::compression_level`].
+    pub fn level(mut self, compression_level: CompressionLevel) -> Self {
+        self.compression_level = Some(compression_level);
+        self
+    }
+
+    /// Set UNIX file mode, see [`ZipFileBuilder::unix_mode`].
+    pub fn unix_mode(mut self, mode: impl Into<UnixMode>) -> Self {
+        self.unix_mode = Some(mode.into());
+        self
+    }
+
+    /// Add additional [`ExtraField`]s, see [`ZipFileBuilder::extra_fields`].
+    pub fn extra_fields(mut self, extra_fields: impl IntoIterator<Item = ExtraField>) -> Self {
+        self.extra_fields.extend(extra_fields);
+        self
+    }
+
+    /// Set file comment, see [`ZipFileBuilder::file_comment`].
+    pub fn file_comment(mut self, comment: String) -> Self {
+        self.file_comment = Some(comment);
+        self
+    }
+
+    /// Set the last modification time, see [`ZipFileBuilder::modification_time`].
+    pub fn modification_time(mut self, time: SystemTime) -> Self {
+        self.modification_time = Some(time);
+        self
+    }
+
+    /// Use a CRC-32 that is known already, see [`ZipFileBuilder::known_crc`].
+    pub fn known_crc(mut self, crc: u32) -> Self {
+        self.known_crc = Some(crc);
+        self
+    }
+
+    /// Mark files as executable or not, see [`ZipFileBuilder::executable`].
+    pub fn executable(mut self, executable: bool) -> Self {
+        self.executable = Some(executable);
+        self
+    }
+
+    /// Set MS-DOS attributes such as `0x01` for read-only or `0x02` for hidden. They replace the
+    /// attributes already set on the file, see [`ZipFileBuilder::dos_attributes`].
+    ///
+    /// ```
+    /// # use mtzip::{FileOptions, ZipArchive};
+    /// let mut zipper = ZipArchive::new();
+    /// let read_only = FileOptions::new().dos_attributes(0x01);
+    /// zipper.add_file_from_memory_with(b"Do not edit".as_slice(), "readonly.txt", &read_only);
+    /// ```
+    pub fn dos_attributes(mut self, dos_attributes: u16) -> Self {
+        self.dos_attributes = Some(dos_attributes);
+        self
+    }
+}
+
+/// Sizes of the compressed entries in an archive, returned by [`ZipArchive::stats`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    /// Total size of the entries before compression
+    pub uncompressed_size: u64,
+    /// Total size of the entries after compression
+    pub compressed_size: u64,
+    /// Amount of entries, including directories
+    pub entries: usize,
+}
+
+impl CompressionStats {
+    /// Compressed size relative to the uncompressed size, `0.38` means that 62% were saved.
+    /// Is `1.0` if there is no uncompressed data.
+    pub fn ratio(&self) -> f64 {
+        if self.uncompressed_size == 0 {
+            1.0
+        } else {
+            self.compressed_size as f64 / self.uncompressed_size as f64
+        }
+    }
+}
+
+/// Information about a compressed entry in an archive, returned by [`ZipArchive::entries`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryInfo<'a> {
+    /// Path of the entry in the archive
+    pub name: &'a str,
+    /// Size of the data after compression
+    pub compressed_size: u64,
+    /// Size of the data before compression
+    pub uncompressed_size: u64,
+    /// CRC-32 of the uncompressed data
+    pub crc: u32,
+    /// Compression type the data is compressed with
+    pub compression: CompressionType,
+}
+
+/// What happened to an entry during compression, returned by [`ZipArchive::compress_report`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryReport {
+    /// Path of the entry in the archive
+    pub name: String,
+    /// Compression type the data ended up compressed with
+    pub compression: CompressionType,
+    /// Size of the data before compression
+    pub uncompressed_size: u64,
+    /// Size of the data after compression
+    pub compressed_size: u64,
+    /// Data was stored as-is because compressing it didn't make it smaller, see
+    /// [`ZipArchive::set_fallback_to_stored`]
+    pub fell_back_to_stored: bool,
 }
 
 /// Builder used to optionally add additional attributes to a file or directory.
@@ -93,18 +443,76 @@ pub struct ZipFileBuilder<'a, 'd, 'p, 'r> {
 impl<'a, 'd, 'p, 'r> ZipFileBuilder<'a, 'd, 'p, 'r> {
     /// Call this when you're done configuring the file entry and it will be added to the job list,
     /// or directly into the resulting dataset if it's a directory. Always needs to be called.
-    pub fn done(self) {
+    ///
+    /// Returns the archive, so that more entries can be added in the same expression.
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// let mut output = std::io::Cursor::new(Vec::new());
+    /// zipper
+    ///     .add_directory("dir".to_owned())
+    ///     .done()
+    ///     .add_file_from_memory(b"Hello, world!".as_slice(), "dir/hello_world.txt".to_owned())
+    ///     .done()
+    ///     .write(&mut output)
+    ///     .unwrap();
+    /// ```
+    pub fn done(self) -> &'a mut ZipArchive<'d, 'p, 'r> {
         let Self {
             archive_handle,
-            job,
+            mut job,
         } = self;
+        // Stored files are copied from the filesystem straight into the output, there is nothing
+        // to gain from reading them into memory in parallel
+        if job.compression_type == CompressionType::Stored
+            && matches!(job.data_origin, ZipJobOrigin::Filesystem { .. })
+        {
+            job.streamed = true;
+        }
         match &job.data_origin {
-            ZipJobOrigin::Directory => {
+            // Queued so that a wrong checksum or compression type is reported when compressing
+            ZipJobOrigin::Precompressed {
+                compression_type, ..
+            } if job.verify_crc || *compression_type == CompressionType::Default => {
+                archive_handle.push_job(job)
+            }
+            ZipJobOrigin::Directory | ZipJobOrigin::Precompressed { .. } => {
                 let file = job.into_file().expect("No failing code path");
                 archive_handle.push_file(file);
             }
             _ => archive_handle.push_job(job),
         }
+        archive_handle
+    }
+
+    /// Apply a set of [`FileOptions`]. Options that are not set in it are left unchanged.
+    pub fn options(mut self, options: &FileOptions) -> Self {
+        if let Some(compression_type) = options.compression_type {
+            self = self.compression_type(compression_type);
+        }
+        if let Some(compression_level) = options.compression_level {
+            self = self.compression_level(compression_level);
+        }
+        if let Some(executable) = options.executable {
+            self = self.executable(executable);
+        }
+        if let Some(unix_mode) = options.unix_mode {
+            self = self.unix_mode(unix_mode);
+        }
+        if let Some(file_comment) = &options.file_comment {
+            self = self.file_comment(file_comment.clone());
+        }
+        if let Some(modification_time) = options.modification_time {
+            self = self.modification_time(modification_time);
+        }
+        if let Some(crc) = options.known_crc {
+            self = self.known_crc(crc);
+        }
+        if let Some(dos_attributes) = options.dos_attributes {
+            self = self.dos_attributes(dos_attributes);
+        }
+        self.extra_fields(options.extra_fields.iter().cloned())
     }
 
     /// Read filesystem metadata from filesystem and add the properties to this file. It sets
@@ -114,17 +522,189 @@ impl<'a, 'd, 'p, 'r> ZipFileBuilder<'a, 'd, 'p, 'r> {
         let metadata = std::fs::metadata(fs_path)?;
         let external_attributes = ZipJob::attributes_from_fs(&metadata);
         let extra_fields = ExtraFields::new_from_fs(&metadata);
-        Ok(self
+        let mut this = self
             .external_attributes(external_attributes)
-            .extra_fields(extra_fields))
+            .extra_fields(extra_fields);
+        this.job.modification_time = ZipJob::modification_time_from_fs(&metadata);
+        Ok(this)
     }
 
-    /// Add a file comment.
+    /// Add a file comment. It is stored in the central directory and must not be longer than
+    /// 65535 bytes, otherwise writing the archive fails with [`Error::CommentTooLong`] before
+    /// anything is written.
     pub fn file_comment(mut self, comment: String) -> Self {
         self.job.file_comment = Some(comment);
         self
     }
 
+    /// Set the last modification time, which is written as MS-DOS time and date fields in the
+    /// headers, and as an [`ExtraField::UnixExtendedTimestamp`] unless the entry already has a
+    /// timestamp extra field or the time is out of its range. Ignored for files added from the
+    /// filesystem, as their modification time is read from the metadata.
+    pub fn modification_time(mut self, time: SystemTime) -> Self {
+        self.job.modification_time = Some(system_time_to_secs(time));
+        self
+    }
+
+    /// Align the start of the file data in the archive to a multiple of `alignment` bytes, by
+    /// padding the local header with an extra field, like `zipalign` does. Mostly useful for
+    /// [stored](CompressionType::Stored) files that are meant to be memory-mapped, such as
+    /// uncompressed files and shared libraries in Android APKs. Overrides the archive-wide
+    /// [stored alignment](ZipArchive::set_stored_alignment).
+    ///
+    /// ```
+    /// # use mtzip::{CompressionType, ZipArchive};
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_memory(b"\x7fELF".as_slice(), "lib/arm64-v8a/libapp.so".to_owned())
+    ///     .compression_type(CompressionType::Stored)
+    ///     .alignment(4096)
+    ///     .done();
+    /// ```
+    pub fn alignment(mut self, alignment: u16) -> Self {
+        self.job.alignment = Some(alignment);
+        self
+    }
+
+    /// Store the file as-is if compressing it doesn't make it smaller, as is usually the case with
+    /// data that is compressed already, such as JPEG images or other archives. Ignored for
+    /// [streamed](Self::streamed) files.
+    ///
+    /// Default is set with [`ZipArchive::set_fallback_to_stored`], which is disabled by default.
+    pub fn fallback_to_stored(mut self, fallback_to_stored: bool) -> Self {
+        self.job.fallback_to_stored = fallback_to_stored;
+        self
+    }
+
+    /// Set additional bits of the general purpose bit flag in both headers, for readers that
+    /// expect specific bits to be set. Bits that depend on how the entry is written are managed
+    /// by this library and ignored: bit 0 (encryption, see
+    /// [`zipcrypto_password`](Self::zipcrypto_password)), bit 3 (data descriptor), bit 6 (strong
+    /// encryption), bit 11 (UTF-8 names, always set) and bit 13 (encrypted central directory).
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_memory(b"Hello, world!".as_slice(), "hello_world.txt".to_owned())
+    ///     // Maximum compression option of deflate
+    ///     .general_purpose_flags(1 << 1)
+    ///     .done();
+    /// ```
+    pub fn general_purpose_flags(mut self, flags: u16) -> Self {
+        self.job.general_purpose_flags = flags;
+        self
+    }
+
+    /// Mark the file as a text file in the internal file attributes, which some extractors use to
+    /// convert line endings. Files are marked as binary by default.
+    pub fn text(mut self, is_text: bool) -> Self {
+        self.job.text = if is_text {
+            TextFlag::Text
+        } else {
+            TextFlag::Binary
+        };
+        self
+    }
+
+    /// Mark the file as a text file if there are no NUL bytes in the first 8 KiB of it, same as
+    /// Info-Zip does. Ignored for [pre-compressed](ZipArchive::add_precompressed) files.
+    pub fn detect_text(mut self) -> Self {
+        self.job.text = TextFlag::Detect;
+        self
+    }
+
+    /// Encrypt the file with traditional PKWARE encryption, also known as ZipCrypto, which most
+    /// extractors support.
+    ///
+    /// **This encryption is broken** and can be cracked easily, only use it when compatibility
+    /// with tools that support nothing else is required. Directories are not encrypted, and
+    /// encrypted files are never [streamed](Self::streamed). The encryption header is random, so
+    /// encrypted files differ between runs even in
+    /// [deterministic](ZipArchive::set_deterministic) mode.
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_memory(b"Hello, world!".as_slice(), "hello_world.txt".to_owned())
+    ///     .zipcrypto_password("password")
+    ///     .done();
+    /// ```
+    pub fn zipcrypto_password(mut self, password: impl Into<Vec<u8>>) -> Self {
+        self.job.password = Some(password.into());
+        self
+    }
+
+    /// Compress this file directly into the output on [`write`](ZipArchive::write), instead of
+    /// compressing it into memory first. Memory usage stays bounded no matter how large the file
+    /// is, at the cost of compressing it on the writing thread after all other files, without any
+    /// parallelism. The local header is filled in afterwards by seeking back in the output. With
+    /// [`write_stream`](ZipArchive::write_stream), which can't seek, the checksum and sizes are
+    /// written in a data descriptor after the data instead.
+    ///
+    /// Files from the filesystem that are [stored](CompressionType::Stored) are always streamed,
+    /// except with [`write_stream`](ZipArchive::write_stream).
+    ///
+    /// Streamed data of unknown length fails with [`Error::EntryTooLarge`] once it reaches 4 GiB,
+    /// since the size of its headers is decided before the data is read. Files from the
+    /// filesystem and [seekable readers](ZipArchive::add_file_from_seekable_reader) don't have
+    /// this limit.
+    ///
+    /// Has no effect when the archive is compressed before writing, such as with
+    /// [`compress`](ZipArchive::compress) or in [deterministic](ZipArchive::set_deterministic)
+    /// mode.
+    ///
+    /// ```no_run
+    /// # use mtzip::ZipArchive;
+    /// # use std::fs::File;
+    /// let mut zipper = ZipArchive::new();
+    /// let huge_file = File::open("huge_file.bin").unwrap();
+    /// zipper
+    ///     .add_file_from_reader(huge_file, "huge_file.bin".to_owned())
+    ///     .streamed()
+    ///     .done();
+    /// let mut output = File::create("output.zip").unwrap();
+    /// zipper.write(&mut output).unwrap();
+    /// ```
+    pub fn streamed(mut self) -> Self {
+        self.job.streamed = true;
+        self
+    }
+
+    /// Add an [`ExtraField::UnicodePath`] with the path of this entry, for compatibility with
+    /// extractors that ignore the UTF-8 flag.
+    pub fn unicode_path_extra_field(mut self) -> Self {
+        self.job.unicode_path = true;
+        self
+    }
+
+    /// Write `name` as the path of this entry instead of the archived path, as-is and without
+    /// setting the UTF-8 flag. Meant for reproducing archives with names in a legacy encoding,
+    /// such as a DOS code page.
+    ///
+    /// The archived path is still used for everything else, such as
+    /// [duplicate checks](ZipArchive::set_reject_duplicates), sorting and
+    /// [`unicode_path_extra_field`](Self::unicode_path_extra_field), so it should be the same
+    /// path in UTF-8. [Path normalization](ZipArchive::set_normalize_paths) doesn't apply to
+    /// `name`.
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_memory(b"Hello, world!".as_slice(), "привет.txt")
+    ///     // Same name in code page 866
+    ///     .raw_name(b"\xAF\xE0\xA8\xA2\xA5\xE2.txt".to_vec())
+    ///     .unicode_path_extra_field()
+    ///     .done();
+    /// let archive = zipper.write_to_vec().unwrap();
+    /// ```
+    pub fn raw_name(mut self, name: impl Into<Vec<u8>>) -> Self {
+        self.job.raw_archive_path = Some(name.into());
+        self
+    }
+
     /// Add additional [`ExtraField`].
     pub fn extra_field(mut self, extra_field: ExtraField) -> Self {
         self.job.extra_fields.values.push(extra_field);
@@ -139,15 +719,82 @@ impl<'a, 'd, 'p, 'r> ZipFileBuilder<'a, 'd, 'p, 'r> {
 
     /// Set compression type. Ignored for directories, as they use no compression.
     ///
-    /// Default is [`CompressionType::Deflate`].
+    /// Default is [`CompressionType::Deflate`], unless a different default is set with
+    /// [`ZipArchive::set_default_compression`]. Empty files are always
+    /// [stored](CompressionType::Stored), as compressing nothing only adds overhead.
+    ///
+    /// ```
+    /// # use mtzip::{CompressionType, ZipArchive};
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_memory(b"".as_slice(), "empty.txt".to_owned())
+    ///     .compression_type(CompressionType::Deflate)
+    ///     .done();
+    /// let mut output = std::io::Cursor::new(Vec::new());
+    /// zipper.write(&mut output).unwrap();
+    ///
+    /// output.set_position(0);
+    /// let zipper = ZipArchive::open_existing(output).unwrap();
+    /// let entry = zipper.entries().next().unwrap();
+    /// assert_eq!(entry.compression, CompressionType::Stored);
+    /// assert_eq!(entry.crc, 0);
+    /// assert_eq!(entry.compressed_size, 0);
+    /// assert_eq!(entry.uncompressed_size, 0);
+    /// ```
     pub fn compression_type(mut self, compression_type: CompressionType) -> Self {
-        self.job.compression_type = compression_type;
+        self.job.compression_type = match &self.archive_handle.default_compression {
+            Some((default_type, _))
+                if compression_type == CompressionType::Default
+                    && self.archive_handle.compression_selector.is_none() =>
+            {
+                *default_type
+            }
+            _ => compression_type,
+        };
+        self
+    }
+
+    /// Set the strategy of the [deflate](CompressionType::Deflate) compressor. Strategies other
+    /// than [`DeflateStrategy::Default`] always use the pure Rust backend, they need the
+    /// `rust_backend` feature and are ignored without it.
+    ///
+    /// ```
+    /// # use mtzip::{DeflateStrategy, ZipArchive};
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_memory(vec![0; 1024], "zeroes.bin".to_owned())
+    ///     .deflate_strategy(DeflateStrategy::Rle)
+    ///     .done();
+    /// ```
+    pub fn deflate_strategy(mut self, deflate_strategy: DeflateStrategy) -> Self {
+        self.job.deflate_strategy = deflate_strategy;
+        self
+    }
+
+    /// Use the CRC-32 of the uncompressed data that is known already, for example from a
+    /// manifest, instead of computing it while compressing. Saves a pass over the data, which
+    /// matters the most for [stored](CompressionType::Stored) files. Ignored for directories and
+    /// precompressed data.
+    ///
+    /// The CRC is not verified, a wrong one makes extracting the file fail.
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_memory(b"Hello, world!".as_slice(), "hello_world.txt".to_owned())
+    ///     .known_crc(0xebe6c6e6)
+    ///     .done();
+    /// ```
+    pub fn known_crc(mut self, crc: u32) -> Self {
+        self.job.known_crc = Some(crc);
         self
     }
 
     /// Set compression level. Ignored for directories, as they use no compression.
     ///
-    /// Default is [`CompressionLevel::best`]
+    /// Default is [`CompressionLevel::best`], unless a different default is set with
+    /// [`ZipArchive::set_default_compression`].
     pub fn compression_level(mut self, compression_level: CompressionLevel) -> Self {
         self.job.compression_level = compression_level;
         self
@@ -155,54 +802,179 @@ impl<'a, 'd, 'p, 'r> ZipFileBuilder<'a, 'd, 'p, 'r> {
 
     /// Set external attributes. The format depends on a filesystem and is mostly a legacy
     /// mechanism, usually a default value is used if this is not a filesystem source. When a file
-    /// is added from the filesystem, these attributes will be read and used and the ones set wit
-    /// hthis method are ignored.
+    /// is added from the filesystem, these attributes will be read and used and the ones set with
+    /// this method are ignored.
+    ///
+    /// The 32-bit external file attributes field is split in two halves:
+    ///
+    /// - the high 16 bits hold the UNIX mode, which is what this method sets. See
+    ///   [`Self::unix_mode`] for a typed alternative.
+    /// - the low 16 bits hold MS-DOS attributes such as read-only or hidden, set with
+    ///   [`Self::dos_attributes`].
     pub fn external_attributes(mut self, external_attributes: u16) -> Self {
         self.job.external_attributes = external_attributes;
         self
     }
 
-    /// Set external file attributes from a filesystem item. Use of this method is discouraged in
-    /// favor of [`Self::metadata_from_fs`], which also sets extra fields which contain modern
-    /// filesystem attributes instead of using old 16-bit system-dependent format.
-    pub fn external_attributes_from_fs(mut self, fs_path: &Path) -> std::io::Result<Self> {
-        let metadata = std::fs::metadata(fs_path)?;
-        self.job.external_attributes = ZipJob::attributes_from_fs(&metadata);
-        Ok(self)
+    /// Set UNIX file mode, such as `0o755` for an executable script. If the mode has no file type
+    /// bits, the regular file or directory type is added depending on the entry.
+    ///
+    /// Same as [`Self::external_attributes`], so it is ignored for files added from the
+    /// filesystem.
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_memory(b"#!/bin/sh\n".as_slice(), "run.sh".to_owned())
+    ///     .unix_mode(0o755)
+    ///     .done();
+    /// ```
+    pub fn unix_mode(mut self, mode: impl Into<UnixMode>) -> Self {
+        let mode = mode.into();
+        self.job.external_attributes = if mode.has_file_type() {
+            mode.get()
+        } else if matches!(self.job.data_origin, ZipJobOrigin::Directory) {
+            mode.get() | UnixMode::DIRECTORY
+        } else {
+            mode.get() | UnixMode::REGULAR_FILE
+        };
+        self
     }
 
-    #[inline]
-    fn new(
-        archive: &'a mut ZipArchive<'d, 'p, 'r>,
-        filename: String,
-        origin: ZipJobOrigin<'d, 'p, 'r>,
+    /// Mark the file as executable or not by setting the UNIX mode to `0o755` or `0o644`, and
+    /// writing UNIX as the host OS of this entry regardless of
+    /// [`ZipArchive::set_host_os`], so that extracting tools apply the mode.
+    ///
+    /// The mode is ignored for files added from the filesystem, like with [`Self::unix_mode`].
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_memory(b"#!/bin/sh\n".as_slice(), "run.sh".to_owned())
+    ///     .executable(true)
+    ///     .done();
+    /// ```
+    pub fn executable(mut self, executable: bool) -> Self {
+        self = self.unix_mode(if executable { 0o755 } else { 0o644 });
+        self.job.host_os = Some(HostOs::Unix);
+        self
+    }
+
+    /// Set MS-DOS attributes, placed into the low 16 bits of the external file attributes without
+    /// affecting the UNIX mode in the high 16 bits. Common bits are `0x01` for read-only, `0x02`
+    /// for hidden, `0x04` for system and `0x20` for archive. Default is 0.
+    ///
+    /// Unlike [`Self::external_attributes`], this also applies to files added from the filesystem.
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_memory(b"Do not edit".as_slice(), "readonly.txt".to_owned())
+    ///     .unix_mode(0o444)
+    ///     .dos_attributes(0x01)
+    ///     .done();
+    /// ```
+    pub fn dos_attributes(mut self, dos_attributes: u16) -> Self {
+        self.job.dos_attributes = dos_attributes;
+        self
+    }
+
+    /// Set external file attributes from a filesystem item. Use of this method is discouraged in
+    /// favor of [`Self::metadata_from_fs`], which also sets extra fields which contain modern
+    /// filesystem attributes instead of using old 16-bit system-dependent format.
+    pub fn external_attributes_from_fs(mut self, fs_path: &Path) -> std::io::Result<Self> {
+        let metadata = std::fs::metadata(fs_path)?;
+        self.job.external_attributes = ZipJob::attributes_from_fs(&metadata);
+        Ok(self)
+    }
+
+    #[inline]
+    fn new(
+        archive: &'a mut ZipArchive<'d, 'p, 'r>,
+        filename: String,
+        origin: ZipJobOrigin<'d, 'p, 'r>,
     ) -> Self {
+        let (mut compression_type, compression_level) = archive
+            .default_compression
+            .unwrap_or((CompressionType::Deflate, CompressionLevel::best()));
+        // The selector is called by the worker, when the file is compressed
+        if archive.compression_selector.is_some() {
+            compression_type = CompressionType::Default;
+        }
+        let compression_selector = archive.compression_selector.clone();
+        let fallback_to_stored = archive.fallback_to_stored;
+        let verify_crc = archive.verify_crc;
+        let max_preallocation = archive.max_preallocation;
         Self {
             archive_handle: archive,
             job: ZipJob {
                 data_origin: origin,
                 archive_path: filename,
+                raw_archive_path: None,
+                host_os: None,
                 extra_fields: ExtraFields::default(),
                 file_comment: None,
+                modification_time: None,
+                unicode_path: false,
+                alignment: None,
+                fallback_to_stored,
+                verify_crc,
+                max_preallocation,
+                text: TextFlag::default(),
+                streamed: false,
+                data_descriptor: false,
+                strip_timestamps: false,
+                password: None,
                 external_attributes: ZipFile::default_file_attrs(),
-                compression_type: CompressionType::Deflate,
-                compression_level: CompressionLevel::best(),
+                compression_type,
+                compression_selector,
+                compression_level,
+                deflate_strategy: DeflateStrategy::default(),
+                known_crc: None,
+                general_purpose_flags: 0,
+                dos_attributes: 0,
             },
         }
     }
 
     #[inline]
     fn new_dir(archive: &'a mut ZipArchive<'d, 'p, 'r>, filename: String) -> Self {
+        // A trailing backslash is a separator too, but only a slash marks a directory entry
+        let mut filename = filename.replace('\\', "/");
+        if !filename.ends_with('/') {
+            filename.push('/');
+        }
         Self {
             archive_handle: archive,
             job: ZipJob {
                 data_origin: ZipJobOrigin::Directory,
                 archive_path: filename,
+                raw_archive_path: None,
+                host_os: None,
                 extra_fields: ExtraFields::default(),
                 file_comment: None,
+                modification_time: None,
+                unicode_path: false,
+                alignment: None,
+                fallback_to_stored: false,
+                verify_crc: false,
+                max_preallocation: None,
+                text: TextFlag::default(),
+                streamed: false,
+                data_descriptor: false,
+                strip_timestamps: false,
+                password: None,
                 external_attributes: ZipFile::default_dir_attrs(),
                 compression_type: CompressionType::Deflate,
+                compression_selector: None,
                 compression_level: CompressionLevel::best(),
+                deflate_strategy: DeflateStrategy::default(),
+                known_crc: None,
+                general_purpose_flags: 0,
+                dos_attributes: 0,
             },
         }
     }
@@ -223,12 +995,29 @@ impl<'a, 'd, 'p, 'r> ZipFileBuilder<'a, 'd, 'p, 'r> {
 ///   [`add_file_from_fs`](Self::add_file_from_fs)
 /// - `'r` is the lifetime of of borrowed data in readers supplied to
 ///   [`add_file_from_reader`](Self::add_file_from_reader)
-#[derive(Debug, Default)]
+#[derive(Derivative, Default)]
+#[derivative(Debug)]
 pub struct ZipArchive<'d, 'p, 'r> {
     jobs_queue: Vec<ZipJob<'d, 'p, 'r>>,
     data: ZipData,
+    reject_duplicates: bool,
+    default_compression: Option<(CompressionType, CompressionLevel)>,
+    fallback_to_stored: bool,
+    verify_crc: bool,
+    max_buffered_bytes: Option<usize>,
+    normalize_paths: bool,
+    thread_count: Option<NonZeroUsize>,
+    symlink_policy: SymlinkPolicy,
+    max_preallocation: Option<usize>,
+    preserve_order: bool,
+    #[derivative(Debug = "ignore")]
+    compression_selector: Option<CompressionSelector>,
 }
 
+/// Chooses the compression type of a file by its path in the archive, shared with the jobs that
+/// call it when they are compressed
+type CompressionSelector = Arc<dyn Fn(&str) -> CompressionType + Send + Sync>;
+
 impl<'d, 'p, 'r> ZipArchive<'d, 'p, 'r> {
     fn push_job(&mut self, job: ZipJob<'d, 'p, 'r>) {
         self.jobs_queue.push(job);
@@ -244,106 +1033,1035 @@ impl<'d, 'p, 'r> ZipArchive<'d, 'p, 'r> {
         Self::default()
     }
 
-    /// Add file from filesystem.
+    /// Add a file that is compressed already, with all of its header fields set by hand. It's
+    /// written as-is, see [`ZipFile`] for what the data has to match.
+    #[inline]
+    pub fn push_prebuilt(&mut self, file: ZipFile) {
+        self.push_file(file);
+    }
+
+    /// Amount of files waiting to be compressed. If it's not zero, [`write`](Self::write) will
+    /// compress them first.
+    #[inline]
+    pub fn queued_jobs(&self) -> usize {
+        self.jobs_queue.len()
+    }
+
+    /// Whether there are files waiting to be compressed, which [`write`](Self::write) compresses
+    /// first. Useful for showing progress before a potentially long call.
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_memory(b"Hello, world!".as_slice(), "hello_world.txt".to_owned())
+    ///     .done();
+    /// assert!(zipper.needs_compression());
+    /// zipper.compress().unwrap();
+    /// assert!(!zipper.needs_compression());
+    /// ```
+    #[inline]
+    pub fn needs_compression(&self) -> bool {
+        !self.jobs_queue.is_empty()
+    }
+
+    /// Amount of entries in the archive, both compressed and waiting to be compressed.
+    #[inline]
+    pub fn entry_count(&self) -> usize {
+        self.data.files.len() + self.jobs_queue.len()
+    }
+
+    /// Whether no entries were added to the archive.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entry_count() == 0
+    }
+
+    /// Remove all entries and reset all settings, such as the archive comment and default
+    /// compression, keeping the allocated memory for reuse.
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// for i in 0..3 {
+    ///     zipper.clear();
+    ///     zipper
+    ///         .add_file_from_memory(b"Hello, world!".as_slice(), "hello_world.txt".to_owned())
+    ///         .done();
+    ///     let mut output = std::io::Cursor::new(Vec::new());
+    ///     zipper.write(&mut output).unwrap();
+    /// }
+    /// ```
+    pub fn clear(&mut self) {
+        self.jobs_queue.clear();
+        self.data.files.clear();
+        self.data.archive_comment = None;
+        self.data.deterministic = false;
+        self.data.strip_timestamps = false;
+        self.data.stored_alignment = None;
+        self.data.host_os = HostOs::default();
+        self.reject_duplicates = false;
+        self.default_compression = None;
+        self.fallback_to_stored = false;
+        self.verify_crc = false;
+        self.max_buffered_bytes = None;
+        self.normalize_paths = false;
+        self.thread_count = None;
+        self.symlink_policy = SymlinkPolicy::default();
+        self.max_preallocation = None;
+        self.preserve_order = false;
+        self.compression_selector = None;
+    }
+
+    /// Remove all entries with the given path in the archive, whether they are compressed already
+    /// or not. A trailing slash is ignored, so directories can be removed by their name. Returns
+    /// whether anything was removed.
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper.add_directory("dir".to_owned()).done();
+    /// zipper
+    ///     .add_file_from_memory(b"old".as_slice(), "dir/file.txt".to_owned())
+    ///     .done();
+    ///
+    /// assert!(zipper.remove("dir/file.txt"));
+    /// assert!(zipper.remove("dir"));
+    /// assert!(!zipper.remove("dir"));
+    /// assert!(zipper.is_empty());
+    /// ```
+    pub fn remove(&mut self, archive_path: &str) -> bool {
+        let archive_path = normalize_archive_path(archive_path);
+        let entry_count = self.entry_count();
+        self.jobs_queue
+            .retain(|job| normalize_archive_path(&job.archive_path) != archive_path);
+        self.data
+            .files
+            .retain(|file| normalize_archive_path(&file.header.filename) != archive_path);
+        self.entry_count() != entry_count
+    }
+
+    /// Move all entries of `other` into this archive, for example to combine archives that were
+    /// built in parallel. Entries that are compressed already are moved as they are, without
+    /// compressing them again. Settings of `other`, such as the archive comment, are discarded.
+    ///
+    /// Duplicate paths are rejected on the next compression or write if
+    /// [`set_reject_duplicates`](Self::set_reject_duplicates) is enabled on this archive.
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_memory(b"first".as_slice(), "first.txt".to_owned())
+    ///     .done();
+    ///
+    /// let mut other = ZipArchive::new();
+    /// other
+    ///     .add_file_from_memory(b"second".as_slice(), "second.txt".to_owned())
+    ///     .done();
+    /// other.compress().unwrap();
+    ///
+    /// zipper.append_archive(other);
+    /// assert_eq!(zipper.entry_count(), 2);
+    /// ```
+    pub fn append_archive(&mut self, mut other: ZipArchive<'d, 'p, 'r>) {
+        self.data.files.append(&mut other.data.files);
+        self.jobs_queue.append(&mut other.jobs_queue);
+    }
+
+    /// Entries that are already compressed. Files waiting to be compressed are not included, so
+    /// call this after [`compress`](Self::compress).
+    ///
+    /// ```
+    /// # use mtzip::{CompressionType, ZipArchive};
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_memory(b"Hello, world!".as_slice(), "hello_world.txt".to_owned())
+    ///     .compression_type(CompressionType::Stored)
+    ///     .done();
+    /// zipper.compress().unwrap();
+    ///
+    /// let entry = zipper.entries().next().unwrap();
+    /// assert_eq!(entry.name, "hello_world.txt");
+    /// assert_eq!(entry.compressed_size, 13);
+    /// assert_eq!(entry.compression, CompressionType::Stored);
+    /// ```
+    pub fn entries(&self) -> impl Iterator<Item = EntryInfo<'_>> {
+        self.data.files.iter().map(|file| EntryInfo {
+            name: &file.header.filename,
+            compressed_size: file.data.len() as u64,
+            uncompressed_size: file.header.uncompressed_size,
+            crc: file.header.crc,
+            compression: file.header.compression_type,
+        })
+    }
+
+    /// Sizes of the entries that are already compressed. Files waiting to be compressed are not
+    /// included, so call this after [`compress`](Self::compress).
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_memory(vec![b'a'; 1000], "a.txt".to_owned())
+    ///     .done();
+    /// zipper.compress().unwrap();
+    ///
+    /// let stats = zipper.stats();
+    /// assert_eq!(stats.uncompressed_size, 1000);
+    /// println!("saved {:.0}%", (1.0 - stats.ratio()) * 100.0);
+    /// ```
+    pub fn stats(&self) -> CompressionStats {
+        self.data
+            .files
+            .iter()
+            .fold(CompressionStats::default(), |stats, file| {
+                CompressionStats {
+                    uncompressed_size: stats.uncompressed_size + file.header.uncompressed_size,
+                    compressed_size: stats.compressed_size + file.data.len() as u64,
+                    entries: stats.entries + 1,
+                }
+            })
+    }
+
+    /// Size of the archive that [`write`](Self::write) would produce now. Exact for the files that
+    /// are already compressed, so call this after [`compress`](Self::compress). Files waiting to
+    /// be compressed are counted with their uncompressed size if it's known without reading them,
+    /// which usually overestimates it, and without the extra fields added while compressing.
+    ///
+    /// Useful for preallocating buffers or reporting the size upfront, for example in a
+    /// `Content-Length` header.
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_memory(vec![b'a'; 1000], "a.txt")
+    ///     .done();
+    /// zipper.compress().unwrap();
+    ///
+    /// let estimated_size = zipper.estimated_size();
+    /// assert_eq!(estimated_size, zipper.write_to_vec().unwrap().len() as u64);
+    /// ```
+    pub fn estimated_size(&self) -> u64 {
+        self.data.estimated_size(&self.jobs_queue)
+    }
+
+    /// Open an existing archive to add more entries to it.
+    ///
+    /// The entries of the existing archive are kept compressed as they are, with their compression
+    /// type, host OS, timestamps, attributes, comments and [`ExtraField`]s preserved. Only stored
+    /// and deflated entries that are not encrypted are supported, and archives split into
+    /// multiple volumes can't be opened. Writing the archive writes the existing entries followed
+    /// by the added ones.
+    ///
+    /// ```no_run
+    /// # use mtzip::ZipArchive;
+    /// # use std::fs::File;
+    /// let mut zipper = ZipArchive::open_existing(File::open("existing.zip").unwrap()).unwrap();
+    /// zipper
+    ///     .add_file_from_memory(b"Hello, world!".as_slice(), "hello_world.txt".to_owned())
+    ///     .done();
+    /// zipper.write(&mut File::create("merged.zip").unwrap()).unwrap();
+    /// ```
+    pub fn open_existing<R: Read + Seek>(mut reader: R) -> Result<Self, Error> {
+        Ok(Self {
+            data: ZipData::read(&mut reader)?,
+            ..Self::default()
+        })
+    }
+
+    /// Set the comment of the whole archive, which is stored at the very end of the archive.
+    ///
+    /// Returns [`Error::CommentTooLong`] if the comment is longer than 65535 bytes. The length is
+    /// stored in the end of central directory record as a 16-bit value, ZIP64 archives have no
+    /// other place for it, so the limit applies to them too.
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper.set_archive_comment("Built by CI").unwrap();
+    /// ```
+    pub fn set_archive_comment(&mut self, comment: impl Into<String>) -> Result<(), Error> {
+        let comment = comment.into();
+        if comment.len() > u16::MAX as usize {
+            return Err(Error::CommentTooLong);
+        }
+        self.data.archive_comment = Some(comment);
+        Ok(())
+    }
+
+    /// Enable or disable deterministic output, for reproducible builds.
+    ///
+    /// When enabled, entries are written sorted by their path in the archive instead of the order
+    /// in which they finished compressing, and timestamps are removed: the MS-DOS modification
+    /// time is set to the earliest possible date and timestamp [`ExtraField`]s are dropped. Other
+    /// extra fields, such as [`ExtraField::UnixAttrs`], are kept.
+    ///
+    /// Because sorting requires all of the files, they are compressed in memory before writing
+    /// rather than written as they are compressed.
+    ///
+    /// Files [encrypted](ZipFileBuilder::zipcrypto_password) with ZipCrypto are not reproducible:
+    /// their encryption header is random, so that files with the same contents and password don't
+    /// encrypt to the same bytes.
+    ///
+    /// Disabled by default.
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.data.deterministic = deterministic;
+    }
+
+    /// Enable or disable removing timestamps from all entries when writing, for minimal archives
+    /// that don't reveal when the files were created, modified or accessed. Same as the
+    /// timestamp handling of [deterministic](Self::set_deterministic) output, without sorting the
+    /// entries: the MS-DOS modification time is set to the earliest possible date and timestamp
+    /// [`ExtraField`]s are dropped.
+    ///
+    /// Disabled by default.
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper.set_strip_timestamps(true);
+    /// zipper
+    ///     .add_file_from_memory(b"Hello, world!".as_slice(), "hello_world.txt")
+    ///     .done();
+    /// let archive = zipper.write_to_vec().unwrap();
+    /// ```
+    pub fn set_strip_timestamps(&mut self, strip_timestamps: bool) {
+        self.data.strip_timestamps = strip_timestamps;
+    }
+
+    /// Enable or disable rejecting entries with duplicate paths. When enabled, compressing or
+    /// writing the archive returns [`Error::DuplicatePath`] if several entries have the same path.
+    /// Backslashes are treated as forward slashes, and a trailing slash is ignored, so a file and
+    /// a directory with the same name are considered duplicates too.
+    ///
+    /// Disabled by default.
+    pub fn set_reject_duplicates(&mut self, reject_duplicates: bool) {
+        self.reject_duplicates = reject_duplicates;
+    }
+
+    /// Normalize paths in the archive when compressing or writing: `\\` separators are replaced
+    /// with `/` as the specification requires, and leading slashes are removed. Paths with `..`
+    /// components are rejected with [`Error::InvalidPath`], as extracting them could write
+    /// outside of the target directory.
+    ///
+    /// Disabled by default.
+    ///
+    /// ```
+    /// # use mtzip::{Error, ZipArchive};
+    /// let mut zipper = ZipArchive::new();
+    /// zipper.set_normalize_paths(true);
+    /// zipper.add_file_from_memory(b"b".as_slice(), "a\\b".to_owned()).done();
+    /// zipper.compress().unwrap();
+    /// assert_eq!(zipper.entries().next().unwrap().name, "a/b");
+    ///
+    /// zipper.add_file_from_memory(b"x".as_slice(), "../x".to_owned()).done();
+    /// assert!(matches!(zipper.compress(), Err(Error::InvalidPath(_))));
+    /// ```
+    pub fn set_normalize_paths(&mut self, normalize_paths: bool) {
+        self.normalize_paths = normalize_paths;
+    }
+
+    /// Limit the amount of compressed data that [`write`](Self::write) holds in memory while
+    /// waiting for it to be written. Threads stop taking new files while the limit is exceeded, so
+    /// it may be exceeded by up to a file per thread. Useful for archiving data that doesn't fit
+    /// into memory when it compresses faster than it can be written.
+    ///
+    /// Has no effect on [`compress`](Self::compress), which keeps all compressed data in memory.
+    /// Without a limit, a couple of compressed files per thread are held in memory at most.
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper.set_max_buffered_bytes(Some(64 * 1024 * 1024));
+    /// ```
+    pub fn set_max_buffered_bytes(&mut self, max_buffered_bytes: Option<usize>) {
+        self.max_buffered_bytes = max_buffered_bytes;
+    }
+
+    /// Set the amount of threads used by [`compress`](Self::compress), [`write`](Self::write) and
+    /// other methods that don't take it as an argument. By default, as many threads as
+    /// [`available_parallelism`](std::thread::available_parallelism) reports are used.
+    ///
+    /// Worker threads are named `mtzip-worker-{i}`, so they are easy to tell apart in profilers
+    /// and debuggers.
+    ///
+    /// ```
+    /// # use std::num::NonZeroUsize;
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper.set_thread_count(NonZeroUsize::new(2));
+    /// ```
+    pub fn set_thread_count(&mut self, thread_count: Option<NonZeroUsize>) {
+        self.thread_count = thread_count;
+    }
+
+    /// Set whether the files are written in the order they were added, even though they are
+    /// compressed in parallel. Otherwise they are written in the order their compression finishes,
+    /// which can differ between runs.
+    ///
+    /// Files are still written as soon as all files added before them are, only the ones that
+    /// finish compressing early are held back. They count towards
+    /// [`set_max_buffered_bytes`](Self::set_max_buffered_bytes), a limit keeps the memory usage
+    /// bounded when a large file is followed by many small ones. Files that were compressed
+    /// beforehand are written first, [streamed](ZipFileBuilder::streamed) files last.
+    ///
+    /// Applies to [`compress`](Self::compress), [`write`](Self::write) and related methods.
+    /// [`compress_with_rayon`](Self::compress_with_rayon) always keeps the order.
+    ///
+    /// Disabled by default.
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper.set_preserve_order(true);
+    /// for name in ["b.txt", "c.txt", "a.txt"] {
+    ///     zipper.add_file_from_memory(name.as_bytes(), name).done();
+    /// }
+    /// let archive = zipper.write_to_vec().unwrap();
+    ///
+    /// let zipper = mtzip::ZipArchive::open_existing(std::io::Cursor::new(archive)).unwrap();
+    /// let names: Vec<_> = zipper.entries().map(|entry| entry.name.to_owned()).collect();
+    /// assert_eq!(names, ["b.txt", "c.txt", "a.txt"]);
+    /// ```
+    pub fn set_preserve_order(&mut self, preserve_order: bool) {
+        self.preserve_order = preserve_order;
+    }
+
+    /// Set whether symbolic links are followed or archived as links by
+    /// [`add_file_from_fs`](Self::add_file_from_fs) and
+    /// [`add_dir_recursive`](Self::add_dir_recursive). Preserving links avoids archiving the same
+    /// data multiple times when several links point to it.
+    ///
+    /// ```no_run
+    /// # use mtzip::{SymlinkPolicy, ZipArchive};
+    /// let mut zipper = ZipArchive::new();
+    /// zipper.set_symlink_policy(SymlinkPolicy::Preserve);
+    /// zipper.add_dir_recursive("release", "", None, None).unwrap();
+    /// ```
+    pub fn set_symlink_policy(&mut self, symlink_policy: SymlinkPolicy) {
+        self.symlink_policy = symlink_policy;
+    }
+
+    /// Set the operating system the archive claims to be made on, for example to make an archive
+    /// with Windows attributes on Linux. Default is the OS this crate is compiled for.
+    ///
+    /// ```
+    /// # use mtzip::{HostOs, ZipArchive};
+    /// let mut zipper = ZipArchive::new();
+    /// zipper.set_host_os(HostOs::Windows);
+    /// zipper
+    ///     .add_file_from_memory(b"Hello, world!".as_slice(), "hello_world.txt".to_owned())
+    ///     .external_attributes(0)
+    ///     .done();
+    /// ```
+    pub fn set_host_os(&mut self, host_os: HostOs) {
+        self.data.host_os = host_os;
+    }
+
+    /// Set the compression type and level used for files added after this call, unless they are
+    /// overridden with [`ZipFileBuilder::compression_type`] and
+    /// [`ZipFileBuilder::compression_level`].
+    ///
+    /// Default is [`CompressionType::Deflate`] with [`CompressionLevel::best`].
+    ///
+    /// ```
+    /// # use mtzip::{level::CompressionLevel, CompressionType, ZipArchive};
+    /// let mut zipper = ZipArchive::new();
+    /// zipper.set_default_compression(CompressionType::Deflate, CompressionLevel::fast());
+    /// zipper
+    ///     .add_file_from_memory(b"Hello, world!".as_slice(), "hello_world.txt".to_owned())
+    ///     .done();
+    /// ```
+    pub fn set_default_compression(
+        &mut self,
+        compression_type: CompressionType,
+        compression_level: CompressionLevel,
+    ) {
+        self.default_compression = Some((compression_type, compression_level));
+    }
+
+    /// Choose the compression type of each file added after this call by its path in the archive,
+    /// such as storing images that are compressed already. Such files are added with
+    /// [`CompressionType::Default`], and the selector is called when they are compressed. It takes
+    /// precedence over the type set with [`set_default_compression`](Self::set_default_compression),
+    /// [`ZipFileBuilder::compression_type`] still overrides it. Directories are always stored and
+    /// are not passed to it.
+    ///
+    /// ```
+    /// # use mtzip::{CompressionType, ZipArchive};
+    /// let mut zipper = ZipArchive::new();
+    /// zipper.set_compression_selector(|path| {
+    ///     if path.ends_with(".png") || path.ends_with(".jpg") {
+    ///         CompressionType::Stored
+    ///     } else {
+    ///         CompressionType::Deflate
+    ///     }
+    /// });
+    /// zipper
+    ///     .add_file_from_memory(b"\x89PNG".as_slice(), "image.png")
+    ///     .done();
+    /// zipper.compress().unwrap();
+    /// assert_eq!(
+    ///     zipper.entries().next().unwrap().compression,
+    ///     CompressionType::Stored
+    /// );
+    /// ```
+    pub fn set_compression_selector(
+        &mut self,
+        selector: impl Fn(&str) -> CompressionType + Send + Sync + 'static,
+    ) {
+        self.compression_selector = Some(Arc::new(selector));
+    }
+
+    /// Store files added after this call as-is if compressing them doesn't make them smaller. Can
+    /// be overridden per file with [`ZipFileBuilder::fallback_to_stored`].
+    ///
+    /// Disabled by default.
+    pub fn set_fallback_to_stored(&mut self, fallback_to_stored: bool) {
+        self.fallback_to_stored = fallback_to_stored;
+    }
+
+    /// Check the CRC-32 of files added after this call against their data once they are
+    /// compressed, by decompressing it again. Compression fails with [`Error::CrcMismatch`] if
+    /// they disagree, which catches wrong [known](ZipFileBuilder::known_crc) or
+    /// [precompressed](Self::add_precompressed) checksums before they end up in the archive.
+    /// Files that are compressed directly into the output when writing are not checked.
+    ///
+    /// Disabled by default, since it costs another pass over the data.
+    ///
+    /// ```
+    /// # use mtzip::{CompressionType, Error, ZipArchive};
+    /// let mut zipper = ZipArchive::new();
+    /// zipper.set_verify_crc(true);
+    /// zipper
+    ///     .add_precompressed(
+    ///         b"Hello, world!".to_vec(),
+    ///         0xDEADBEEF,
+    ///         13,
+    ///         CompressionType::Stored,
+    ///         "hello_world.txt",
+    ///     )
+    ///     .done();
+    /// match zipper.compress() {
+    ///     Err(Error::CrcMismatch(path)) => assert_eq!(path, "hello_world.txt"),
+    ///     _ => unreachable!(),
+    /// }
+    /// ```
+    pub fn set_verify_crc(&mut self, verify_crc: bool) {
+        self.verify_crc = verify_crc;
+    }
+
+    /// Limit how much memory is allocated upfront for compressing each file added after this
+    /// call. By default, as much as the size of the file is allocated, which can be huge if the
+    /// filesystem reports a wrong size, as some special files do. The buffer grows as needed
+    /// beyond the limit.
+    ///
+    /// Unlimited by default.
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper.set_max_preallocation(Some(64 * 1024));
+    /// ```
+    pub fn set_max_preallocation(&mut self, max_preallocation: Option<usize>) {
+        self.max_preallocation = max_preallocation;
+    }
+
+    /// Set the alignment of the data of [stored](CompressionType::Stored) files that have no
+    /// alignment set with [`ZipFileBuilder::alignment`]. Android APKs need 4 byte alignment.
+    ///
+    /// Disabled by default.
+    pub fn set_stored_alignment(&mut self, alignment: Option<u16>) {
+        self.data.stored_alignment = alignment;
+    }
+
+    /// Add file from filesystem.
+    ///
+    /// Opens the file and reads data from it when [`compress`](Self::compress) is called. If the
+    /// path is a symbolic link and [`SymlinkPolicy::Preserve`] is set, the link is added as with
+    /// [`add_symlink`](Self::add_symlink) instead.
+    ///
+    /// The file is read until the end, the size reported by the filesystem is not relied upon.
+    /// Special files such as the ones in `/proc` or named pipes can be added too.
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// # use std::path::Path;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_fs(Path::new("input.txt"), "input.txt".to_owned())
+    ///     .done();
+    /// ```
+    #[inline]
+    pub fn add_file_from_fs(
+        &mut self,
+        fs_path: impl Into<Cow<'p, Path>>,
+        archived_path: impl Into<String>,
+    ) -> ZipFileBuilder<'_, 'd, 'p, 'r> {
+        let path = fs_path.into();
+        if self.symlink_policy == SymlinkPolicy::Preserve {
+            if let Some(target) = symlink_target(&path) {
+                return self.add_symlink(archived_path, target);
+            }
+        }
+        ZipFileBuilder::new(
+            self,
+            archived_path.into(),
+            ZipJobOrigin::Filesystem { path },
+        )
+    }
+
+    /// Add file from filesystem, using its file name as the path in the archive. Same as
+    /// [`add_file_from_fs`](Self::add_file_from_fs) otherwise.
+    ///
+    /// Returns [`Error::InvalidFileName`] if the path has no file name, such as `..`, or if the
+    /// file name is not valid UTF-8.
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// # use std::path::Path;
+    /// let mut zipper = ZipArchive::new();
+    /// // Archived as `input.txt`
+    /// zipper
+    ///     .add_file_from_path_with_name(Path::new("data/input.txt"))
+    ///     .unwrap()
+    ///     .done();
+    /// ```
+    pub fn add_file_from_path_with_name(
+        &mut self,
+        fs_path: impl Into<Cow<'p, Path>>,
+    ) -> Result<ZipFileBuilder<'_, 'd, 'p, 'r>, Error> {
+        let path = fs_path.into();
+        let archived_path = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| Error::InvalidFileName(path.to_path_buf()))?
+            .to_owned();
+        Ok(self.add_file_from_fs(path, archived_path))
+    }
+
+    /// Add file with data from memory.
+    ///
+    /// The data can be either borrowed or owned by the [`ZipArchive`] struct to avoid lifetime
+    /// hell.
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// # use std::path::Path;
+    /// let mut zipper = ZipArchive::new();
+    /// let data: &[u8] = "Hello, world!".as_ref();
+    /// zipper
+    ///     .add_file_from_memory(data, "hello_world.txt")
+    ///     .done();
+    /// ```
+    #[inline]
+    pub fn add_file_from_memory(
+        &mut self,
+        data: impl Into<Cow<'d, [u8]>>,
+        archived_path: impl Into<String>,
+    ) -> ZipFileBuilder<'_, 'd, 'p, 'r> {
+        ZipFileBuilder::new(
+            self,
+            archived_path.into(),
+            ZipJobOrigin::RawData(data.into()),
+        )
+    }
+
+    /// Add file with data from memory and apply [`FileOptions`] to it. Same as
+    /// [`add_file_from_memory`](Self::add_file_from_memory) followed by
+    /// [`ZipFileBuilder::options`] and [`ZipFileBuilder::done`].
+    #[inline]
+    pub fn add_file_from_memory_with(
+        &mut self,
+        data: impl Into<Cow<'d, [u8]>>,
+        archived_path: impl Into<String>,
+        options: &FileOptions,
+    ) -> &mut Self {
+        self.add_file_from_memory(data, archived_path)
+            .options(options)
+            .done()
+    }
+
+    /// Add multiple files with data from memory, applying the same [`FileOptions`] to each of
+    /// them. Same as calling [`add_file_from_memory_with`](Self::add_file_from_memory_with) for
+    /// each `(archived_path, data)` pair.
+    ///
+    /// ```
+    /// # use mtzip::{CompressionType, FileOptions, ZipArchive};
+    /// let mut zipper = ZipArchive::new();
+    /// let options = FileOptions::new().compression(CompressionType::Stored);
+    /// zipper.add_files_from_memory(
+    ///     [
+    ///         ("first.txt", b"first".as_slice()),
+    ///         ("second.txt", b"second".as_slice()),
+    ///     ],
+    ///     &options,
+    /// );
+    /// assert_eq!(zipper.queued_jobs(), 2);
+    /// ```
+    pub fn add_files_from_memory<P: Into<String>, D: Into<Cow<'d, [u8]>>>(
+        &mut self,
+        files: impl IntoIterator<Item = (P, D)>,
+        options: &FileOptions,
+    ) {
+        for (archived_path, data) in files {
+            self.add_file_from_memory_with(data, archived_path, options);
+        }
+    }
+
+    /// Add the files of an in-memory file tree, mapping paths in the archive to file contents,
+    /// applying the same [`FileOptions`] to each of them. The files are queued sorted by path, so
+    /// that the output doesn't depend on the iteration order of the map.
+    ///
+    /// ```
+    /// # use std::collections::HashMap;
+    /// # use mtzip::{FileOptions, ZipArchive};
+    /// let files = HashMap::from([
+    ///     ("src/main.rs".to_owned(), b"fn main() {}".to_vec()),
+    ///     ("Cargo.toml".to_owned(), b"[package]".to_vec()),
+    /// ]);
+    /// let mut zipper = ZipArchive::new();
+    /// zipper.add_files_from_map(files, &FileOptions::new());
+    /// assert_eq!(zipper.queued_jobs(), 2);
+    /// ```
+    pub fn add_files_from_map(&mut self, map: HashMap<String, Vec<u8>>, options: &FileOptions) {
+        let mut files: Vec<_> = map.into_iter().collect();
+        files.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        self.add_files_from_memory(files, options);
+    }
+
+    /// Add a file with data from a reader.
+    ///
+    /// This method takes any type implementing [`Read`] and allows it to have borrowed data (`'r`)
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// # use std::path::Path;
+    /// let mut zipper = ZipArchive::new();
+    /// let data_input = std::io::stdin();
+    /// zipper
+    ///     .add_file_from_reader(data_input, "stdin_file.txt")
+    ///     .done();
+    /// ```
+    #[inline]
+    pub fn add_file_from_reader<R: Read + Send + Sync + UnwindSafe + RefUnwindSafe + 'r>(
+        &mut self,
+        reader: R,
+        archived_path: impl Into<String>,
+    ) -> ZipFileBuilder<'_, 'd, 'p, 'r> {
+        ZipFileBuilder::new(
+            self,
+            archived_path.into(),
+            ZipJobOrigin::Reader {
+                reader: Box::new(reader),
+                len: None,
+            },
+        )
+    }
+
+    /// Add a file with data from a seekable reader, from its current position to the end.
+    ///
+    /// Unlike with [`add_file_from_reader`](Self::add_file_from_reader), the length of the data is
+    /// found by seeking to the end, so that the memory for compressing it is allocated upfront and
+    /// streamed entries of 4 GiB or more get room for ZIP64 sizes in their headers. The reader is
+    /// seeked back before returning. Together with [`ZipFileBuilder::streamed`], a part of a larger
+    /// file can be archived without holding a copy of it in memory.
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// # use std::io::{Cursor, Seek, SeekFrom};
+    /// let mut zipper = ZipArchive::new();
+    /// let mut source = Cursor::new(b"header, Hello, world!".as_slice());
+    /// source.seek(SeekFrom::Start(8)).unwrap();
+    /// zipper
+    ///     .add_file_from_seekable_reader(source, "hello_world.txt")
+    ///     .unwrap()
+    ///     .streamed()
+    ///     .done();
+    /// ```
+    pub fn add_file_from_seekable_reader<
+        R: Read + Seek + Send + Sync + UnwindSafe + RefUnwindSafe + 'r,
+    >(
+        &mut self,
+        mut reader: R,
+        archived_path: impl Into<String>,
+    ) -> Result<ZipFileBuilder<'_, 'd, 'p, 'r>, Error> {
+        let start = reader.stream_position()?;
+        let end = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(start))?;
+        Ok(ZipFileBuilder::new(
+            self,
+            archived_path.into(),
+            ZipJobOrigin::Reader {
+                reader: Box::new(reader),
+                len: Some(end.saturating_sub(start)),
+            },
+        ))
+    }
+
+    /// Add a file with data that is already compressed, such as a cached result of an earlier
+    /// compression, without compressing it again. `crc` and `uncompressed_size` have to be those
+    /// of the uncompressed data, and `compression_type` the method `compressed_data` was
+    /// compressed with. [Raw deflate](flate2::write::DeflateEncoder) data is expected for
+    /// [`CompressionType::Deflate`], without zlib or gzip headers.
+    ///
+    /// The entry is added as-is, [`ZipFileBuilder::compression_type`] and
+    /// [`ZipFileBuilder::compression_level`] are ignored for it.
+    ///
+    /// ```
+    /// # use mtzip::{CompressionType, ZipArchive};
+    /// # use std::io::Write;
+    /// let data = b"Hello, world!";
+    /// let mut encoder =
+    ///     flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+    /// encoder.write_all(data).unwrap();
+    /// let compressed = encoder.finish().unwrap();
+    /// let mut crc = flate2::Crc::new();
+    /// crc.update(data);
+    ///
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_precompressed(
+    ///         compressed,
+    ///         crc.sum(),
+    ///         data.len() as u64,
+    ///         CompressionType::Deflate,
+    ///         "hello_world.txt",
+    ///     )
+    ///     .done();
+    /// ```
+    #[inline]
+    pub fn add_precompressed(
+        &mut self,
+        compressed_data: Vec<u8>,
+        crc: u32,
+        uncompressed_size: u64,
+        compression_type: CompressionType,
+        archived_path: impl Into<String>,
+    ) -> ZipFileBuilder<'_, 'd, 'p, 'r> {
+        ZipFileBuilder::new(
+            self,
+            archived_path.into(),
+            ZipJobOrigin::Precompressed {
+                data: compressed_data,
+                crc,
+                uncompressed_size,
+                compression_type,
+            },
+        )
+    }
+
+    /// Add a directory entry.
+    ///
+    /// All directories in the tree should be added. This method does not asssociate any filesystem
+    /// properties to the entry.
     ///
-    /// Opens the file and reads data from it when [`compress`](Self::compress) is called.
+    /// Paths of directories always end with a slash, it's added if missing after backslashes are
+    /// replaced with slashes, and paths of files never do. Compressing or writing the archive
+    /// returns [`Error::InvalidPath`] for a file path that ends with a slash, and
+    /// [`Error::PathConflict`] if a path is used both for a file and a directory, including as a
+    /// parent directory of another entry. Such archives would be extracted differently by
+    /// different tools.
     ///
     /// ```
-    /// # use mtzip::ZipArchive;
+    /// # use mtzip::{Error, ZipArchive};
     /// # use std::path::Path;
     /// let mut zipper = ZipArchive::new();
-    /// zipper
-    ///     .add_file_from_fs(Path::new("input.txt"), "input.txt".to_owned())
-    ///     .done();
+    /// zipper.add_directory("test_dir").done();
+    /// zipper.compress().unwrap();
+    /// assert_eq!(zipper.entries().next().unwrap().name, "test_dir/");
+    ///
+    /// zipper.add_file_from_memory(b"Hello".as_slice(), "test_dir").done();
+    /// assert!(matches!(zipper.compress(), Err(Error::PathConflict(_))));
     /// ```
     #[inline]
-    pub fn add_file_from_fs(
+    pub fn add_directory(
         &mut self,
-        fs_path: impl Into<Cow<'p, Path>>,
-        archived_path: String,
+        archived_path: impl Into<String>,
     ) -> ZipFileBuilder<'_, 'd, 'p, 'r> {
-        ZipFileBuilder::new(
-            self,
-            archived_path,
-            ZipJobOrigin::Filesystem {
-                path: fs_path.into(),
-            },
-        )
+        ZipFileBuilder::new_dir(self, archived_path.into())
     }
 
-    /// Add file with data from memory.
+    /// Add a symbolic link entry pointing to `target`.
     ///
-    /// The data can be either borrowed or owned by the [`ZipArchive`] struct to avoid lifetime
-    /// hell.
+    /// The entry is stored uncompressed with the link target as its data and the UNIX symlink file
+    /// type in the external attributes, which is how Info-Zip encodes symlinks. Extractors that
+    /// understand UNIX attributes, such as `unzip`, recreate it as a symlink.
     ///
     /// ```
     /// # use mtzip::ZipArchive;
-    /// # use std::path::Path;
     /// let mut zipper = ZipArchive::new();
-    /// let data: &[u8] = "Hello, world!".as_ref();
     /// zipper
-    ///     .add_file_from_memory(data, "hello_world.txt".to_owned())
+    ///     .add_symlink("latest", "releases/1.0.0")
     ///     .done();
     /// ```
     #[inline]
-    pub fn add_file_from_memory(
+    pub fn add_symlink(
         &mut self,
-        data: impl Into<Cow<'d, [u8]>>,
-        archived_path: String,
+        archived_path: impl Into<String>,
+        target: impl Into<Vec<u8>>,
     ) -> ZipFileBuilder<'_, 'd, 'p, 'r> {
-        ZipFileBuilder::new(self, archived_path, ZipJobOrigin::RawData(data.into()))
+        ZipFileBuilder::new(
+            self,
+            archived_path.into(),
+            ZipJobOrigin::RawData(Cow::Owned(target.into())),
+        )
+        .compression_type(CompressionType::Stored)
+        .external_attributes(DEFAULT_UNIX_SYMLINK_ATTRS)
     }
 
-    /// Add a file with data from a reader.
+    /// Add the AppleDouble entry with the Finder info of the entry at `archived_path`, as macOS
+    /// Archive Utility does. It's stored at `__MACOSX/<parent>/._<name>`, which Archive Utility
+    /// merges back into the extracted file, and other extractors extract as a separate file.
     ///
-    /// This method takes any type implementing [`Read`] and allows it to have borrowed data (`'r`)
+    /// `finder_info` is the 32 bytes of `FileInfo` and `ExtendedFileInfo`, such as the type and
+    /// creator codes and the Finder flags. The entry has an empty resource fork.
     ///
     /// ```
     /// # use mtzip::ZipArchive;
-    /// # use std::path::Path;
     /// let mut zipper = ZipArchive::new();
-    /// let data_input = std::io::stdin();
     /// zipper
-    ///     .add_file_from_reader(data_input, "stdin_file.txt".to_owned())
+    ///     .add_file_from_memory(b"Hello, world!".as_slice(), "docs/hello.txt")
+    ///     .done();
+    /// let mut finder_info = [0; 32];
+    /// // Type and creator codes
+    /// finder_info[..8].copy_from_slice(b"TEXTttxt");
+    /// zipper
+    ///     .add_apple_double("docs/hello.txt", &finder_info)
     ///     .done();
+    /// zipper.compress().unwrap();
+    /// assert!(zipper.entries().any(|entry| entry.name == "__MACOSX/docs/._hello.txt"));
     /// ```
-    #[inline]
-    pub fn add_file_from_reader<R: Read + Send + Sync + UnwindSafe + RefUnwindSafe + 'r>(
+    pub fn add_apple_double(
         &mut self,
-        reader: R,
-        archived_path: String,
+        archived_path: &str,
+        finder_info: &[u8; 32],
     ) -> ZipFileBuilder<'_, 'd, 'p, 'r> {
-        ZipFileBuilder::new(self, archived_path, ZipJobOrigin::Reader(Box::new(reader)))
+        ZipFileBuilder::new(
+            self,
+            apple_double_path(archived_path),
+            ZipJobOrigin::RawData(Cow::Owned(apple_double(finder_info))),
+        )
+        .compression_type(CompressionType::Stored)
     }
 
-    /// Add a directory entry.
+    /// Recursively add a directory tree from filesystem.
     ///
-    /// All directories in the tree should be added. This method does not asssociate any filesystem
-    /// properties to the entry.
+    /// Every file and subdirectory of `fs_root` is added with a path relative to `fs_root`, placed
+    /// under `archive_prefix` in the archive. If `archive_prefix` is not empty, a directory entry
+    /// for it is added as well. Filesystem metadata is added to all entries, as with
+    /// [`ZipFileBuilder::metadata_from_fs`]. Archive paths always use forward slashes.
     ///
-    /// ```
+    /// Symlinks to files are followed, symlinks to directories are skipped to avoid loops. With
+    /// [`SymlinkPolicy::Preserve`], all symlinks are added as links instead. The compression type
+    /// and level are applied to every file, if specified.
+    ///
+    /// Returns [`Error::InvalidFileName`] if a file name in the tree is not valid UTF-8, and
+    /// [`Error::Io`] if the tree can't be read.
+    ///
+    /// ```no_run
     /// # use mtzip::ZipArchive;
-    /// # use std::path::Path;
     /// let mut zipper = ZipArchive::new();
-    /// zipper.add_directory("test_dir/".to_owned()).done();
+    /// zipper.add_dir_recursive("assets", "assets", None, None).unwrap();
     /// ```
-    #[inline]
-    pub fn add_directory(&mut self, archived_path: String) -> ZipFileBuilder<'_, 'd, 'p, 'r> {
-        ZipFileBuilder::new_dir(self, archived_path)
+    pub fn add_dir_recursive(
+        &mut self,
+        fs_root: impl AsRef<Path>,
+        archive_prefix: &str,
+        compression: Option<CompressionType>,
+        level: Option<CompressionLevel>,
+    ) -> Result<(), Error> {
+        let fs_root = fs_root.as_ref();
+        let archive_prefix = archive_prefix.trim_end_matches('/');
+        if !archive_prefix.is_empty() {
+            self.add_directory(archive_prefix.to_owned())
+                .metadata_from_fs(fs_root)?
+                .done();
+        }
+        self.add_dir_contents(fs_root, archive_prefix, compression, level)
+    }
+
+    fn add_dir_contents(
+        &mut self,
+        fs_dir: &Path,
+        archive_dir: &str,
+        compression: Option<CompressionType>,
+        level: Option<CompressionLevel>,
+    ) -> Result<(), Error> {
+        let mut entries = std::fs::read_dir(fs_dir)?.collect::<std::io::Result<Vec<_>>>()?;
+        // Sorted for consistent order of entries
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let name = entry
+                .file_name()
+                .into_string()
+                .map_err(|_| Error::InvalidFileName(entry.path()))?;
+            let archived_path = if archive_dir.is_empty() {
+                name
+            } else {
+                format!("{archive_dir}/{name}")
+            };
+            let fs_path = entry.path();
+            let file_type = entry.file_type()?;
+
+            if file_type.is_symlink() && self.symlink_policy == SymlinkPolicy::Preserve {
+                let target = link_target_bytes(std::fs::read_link(&fs_path)?);
+                self.add_symlink(archived_path, target).done();
+            } else if file_type.is_dir() {
+                self.add_directory(archived_path.clone())
+                    .metadata_from_fs(&fs_path)?
+                    .done();
+                self.add_dir_contents(&fs_path, &archived_path, compression, level)?;
+            } else if fs_path.is_file() {
+                let mut builder = self.add_file_from_fs(fs_path, archived_path);
+                if let Some(compression) = compression {
+                    builder = builder.compression_type(compression);
+                }
+                if let Some(level) = level {
+                    builder = builder.compression_level(level);
+                }
+                builder.done();
+            }
+        }
+
+        Ok(())
     }
 
     /// Compress contents. Will be done automatically on [`write`](Self::write) call if files were
     /// added between last compression and [`write`](Self::write) call. Automatically chooses
-    /// amount of threads to use based on how much are available.
+    /// amount of threads to use based on how much are available, unless it was set with
+    /// [`set_thread_count`](Self::set_thread_count).
+    ///
+    /// Returns the first error encountered while reading or compressing the files, for example
+    /// [`Error::OpenInput`] if a file added from the filesystem no longer exists. No more files
+    /// are started then, but the ones that were being compressed at that moment are kept. Files
+    /// that weren't started stay queued, so that calling this again after fixing the problem
+    /// compresses the rest of them. The file that failed is discarded.
+    ///
+    /// ```
+    /// # use mtzip::{Error, ZipArchive};
+    /// # use std::path::Path;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_fs(Path::new("missing.txt"), "docs/missing.txt")
+    ///     .done();
+    /// match zipper.compress() {
+    ///     Err(Error::OpenInput { archive_path, .. }) => assert_eq!(archive_path, "docs/missing.txt"),
+    ///     _ => unreachable!(),
+    /// }
+    /// ```
     #[inline]
-    pub fn compress(&mut self) {
-        self.compress_with_threads(Self::get_threads());
+    pub fn compress(&mut self) -> Result<(), Error> {
+        self.compress_with_threads(self.get_threads())
     }
 
     /// Compress contents. Will be done automatically on
     /// [`write_with_threads`](Self::write_with_threads) call if files were added between last
     /// compression and [`write`](Self::write). Allows specifying amount of threads that will be
-    /// used.
+    /// used, 0 is treated as 1.
     ///
     /// Example of getting amount of threads that this library uses in
     /// [`compress`](Self::compress):
@@ -356,27 +2074,154 @@ impl<'d, 'p, 'r> ZipArchive<'d, 'p, 'r> {
     ///     .map(NonZeroUsize::get)
     ///     .unwrap_or(1);
     ///
-    /// zipper.compress_with_threads(threads);
+    /// zipper.compress_with_threads(threads).unwrap();
     /// ```
     #[inline]
-    pub fn compress_with_threads(&mut self, threads: usize) {
-        if !self.jobs_queue.is_empty() {
-            self.compress_with_consumer(threads, |zip_data, rx| zip_data.files.extend(rx))
+    pub fn compress_with_threads(&mut self, threads: usize) -> Result<(), Error> {
+        self.prepare_paths()?;
+        if self.jobs_queue.is_empty() {
+            return Ok(());
+        }
+        let failed = AtomicBool::new(false);
+        self.compress_with_consumer(threads, &[&failed], |zip_data, rx| {
+            Self::collect_compressed(zip_data, rx, &failed)
+        })
+    }
+
+    /// Keep all files that were compressed, even after an error. The first error is returned, and
+    /// `failed` is set then so that the workers don't start compressing more files.
+    fn collect_compressed(
+        zip_data: &mut ZipData,
+        rx: LimitedReceiver<'_>,
+        failed: &AtomicBool,
+    ) -> Result<(), Error> {
+        let mut result = Ok(());
+        for file in rx {
+            match file {
+                Ok(file) => zip_data.files.push(file),
+                Err(err) if result.is_ok() => {
+                    failed.store(true, Ordering::Relaxed);
+                    result = Err(err.into());
+                }
+                Err(_) => {}
+            }
+        }
+        result
+    }
+
+    /// Same as [`compress_with_threads`](Self::compress_with_threads), but stops once `cancel` is
+    /// set, for example from another thread when the user aborts the operation. Files that are
+    /// being compressed at that moment are finished first.
+    ///
+    /// Returns [`Error::Cancelled`] if cancelled. All files that were waiting to be compressed are
+    /// discarded then, files that were already compressed are kept. On other errors, as with
+    /// [`compress_with_threads`](Self::compress_with_threads), the files that were compressed are
+    /// kept and the ones that weren't started stay queued.
+    ///
+    /// ```
+    /// # use mtzip::{Error, ZipArchive};
+    /// # use std::sync::atomic::AtomicBool;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_memory(b"Hello, world!".as_slice(), "hello_world.txt".to_owned())
+    ///     .done();
+    ///
+    /// let cancel = AtomicBool::new(true);
+    /// let result = zipper.compress_with_cancellation(4, &cancel);
+    /// assert!(matches!(result, Err(Error::Cancelled)));
+    /// assert!(zipper.is_empty());
+    /// ```
+    pub fn compress_with_cancellation(
+        &mut self,
+        threads: usize,
+        cancel: &AtomicBool,
+    ) -> Result<(), Error> {
+        self.prepare_paths()?;
+        if self.jobs_queue.is_empty() {
+            return Ok(());
+        }
+        let failed = AtomicBool::new(false);
+        let result = self.compress_with_consumer(threads, &[cancel, &failed], |zip_data, rx| {
+            Self::collect_compressed(zip_data, rx, &failed)
+        });
+        if cancel.load(Ordering::Relaxed) {
+            self.jobs_queue.clear();
+            return Err(Error::Cancelled);
         }
+        result
+    }
+
+    /// Same as [`compress_with_threads`](Self::compress_with_threads), but also returns what
+    /// happened to each file compressed by this call, for example to print a summary per file.
+    /// Entries that were already compressed, including directories, are not included.
+    ///
+    /// ```
+    /// # use mtzip::{CompressionType, ZipArchive};
+    /// let mut zipper = ZipArchive::new();
+    /// zipper.set_fallback_to_stored(true);
+    /// zipper
+    ///     .add_file_from_memory(vec![b'a'; 1000], "a.txt".to_owned())
+    ///     .done();
+    /// zipper
+    ///     .add_file_from_memory(b"x".as_slice(), "x.txt".to_owned())
+    ///     .done();
+    ///
+    /// let mut report = zipper.compress_report(4).unwrap();
+    /// report.sort_by(|a, b| a.name.cmp(&b.name));
+    /// assert_eq!(report[0].compression, CompressionType::Deflate);
+    /// assert!(report[0].compressed_size < 1000);
+    /// assert_eq!(report[1].compression, CompressionType::Stored);
+    /// assert!(report[1].fell_back_to_stored);
+    /// ```
+    pub fn compress_report(&mut self, threads: usize) -> Result<Vec<EntryReport>, Error> {
+        let already_compressed = self.data.files.len();
+        self.compress_with_threads(threads)?;
+        Ok(self.data.files[already_compressed..]
+            .iter()
+            .map(|file| EntryReport {
+                name: file.header.filename.clone(),
+                compression: file.header.compression_type,
+                uncompressed_size: file.header.uncompressed_size,
+                compressed_size: file.data.len() as u64,
+                fell_back_to_stored: file.header.stored_fallback,
+            })
+            .collect())
     }
 
     /// Write compressed data to a writer (usually a file). Executes [`compress`](Self::compress)
     /// if files were added between last [`compress`](Self::compress) call and this call.
-    /// Automatically chooses the amount of threads cpu has.
+    /// Automatically chooses the amount of threads cpu has, unless it was set with
+    /// [`set_thread_count`](Self::set_thread_count).
+    ///
+    /// Files that weren't compressed beforehand are written as soon as they are compressed, and
+    /// only their metadata is kept in memory afterwards. At most a couple of compressed files per
+    /// thread are held in memory at once, so calling this without [`compress`](Self::compress)
+    /// is preferable for large archives.
+    ///
+    /// Writes are buffered internally, so there is no need to wrap the writer in a
+    /// [`BufWriter`](std::io::BufWriter).
+    ///
+    /// Returns the amount of bytes written.
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_memory(b"Hello, world!".as_slice(), "hello_world.txt".to_owned())
+    ///     .done();
+    /// let mut output = std::io::Cursor::new(Vec::new());
+    /// let written = zipper.write(&mut output).unwrap();
+    /// assert_eq!(written, output.get_ref().len() as u64);
+    /// ```
     #[inline]
-    pub fn write<W: Write + Seek>(&mut self, writer: &mut W) -> std::io::Result<()> {
-        self.write_with_threads(writer, Self::get_threads())
+    pub fn write<W: Write + Seek>(&mut self, writer: &mut W) -> Result<u64, Error> {
+        self.write_with_threads(writer, self.get_threads())
     }
 
     /// Write compressed data to a writer (usually a file). Executes
     /// [`compress_with_threads`](Self::compress_with_threads) if files were added between last
     /// [`compress`](Self::compress) call and this call. Allows specifying amount of threads that
-    /// will be used.
+    /// will be used, 0 is treated as 1.
     ///
     /// Example of getting amount of threads that this library uses in [`write`](Self::write):
     ///
@@ -388,55 +2233,481 @@ impl<'d, 'p, 'r> ZipArchive<'d, 'p, 'r> {
     ///     .map(NonZeroUsize::get)
     ///     .unwrap_or(1);
     ///
-    /// zipper.compress_with_threads(threads);
+    /// zipper.compress_with_threads(threads).unwrap();
+    /// ```
+    ///
+    /// No files are lost when passing 0:
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper.add_file_from_memory(b"first".as_slice(), "first.txt").done();
+    /// zipper.add_file_from_memory(b"second".as_slice(), "second.txt").done();
+    /// let mut output = std::io::Cursor::new(Vec::new());
+    /// zipper.write_with_threads(&mut output, 0).unwrap();
+    ///
+    /// output.set_position(0);
+    /// assert_eq!(ZipArchive::open_existing(output).unwrap().entry_count(), 2);
     /// ```
     #[inline]
     pub fn write_with_threads<W: Write + Seek>(
         &mut self,
         writer: &mut W,
         threads: usize,
-    ) -> std::io::Result<()> {
+    ) -> Result<u64, Error> {
+        self.prepare_paths()?;
+        if self.data.deterministic {
+            // All files have to be compressed before they can be sorted
+            self.compress_with_threads(threads)?;
+        }
+        let streamed_jobs = self.take_streamed_jobs();
         if !self.jobs_queue.is_empty() {
-            self.compress_with_consumer(threads, |zip_data, rx| zip_data.write(writer, rx))
+            self.compress_with_consumer(threads, &[], |zip_data, rx| {
+                zip_data.write(writer, rx, streamed_jobs)
+            })
         } else {
-            self.data.write(writer, std::iter::empty())
+            self.data.write(writer, std::iter::empty(), streamed_jobs)
+        }
+    }
+
+    /// Write the archive to a file at `path`, creating or truncating it. Same as
+    /// [`write`](Self::write) with a newly created file.
+    ///
+    /// ```no_run
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_memory(b"Hello, world!".as_slice(), "hello_world.txt".to_owned())
+    ///     .done();
+    /// zipper.write_to_path("output.zip").unwrap();
+    /// ```
+    pub fn write_to_path(&mut self, path: impl AsRef<Path>) -> Result<u64, Error> {
+        self.write(&mut std::fs::File::create(path)?)
+    }
+
+    /// Write the archive into a new [`Vec`]. Same as [`write`](Self::write) with a
+    /// [`Cursor`](std::io::Cursor) over an empty vector. Space for the entries that are already
+    /// compressed is allocated upfront.
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_memory(b"Hello, world!".as_slice(), "hello_world.txt".to_owned())
+    ///     .done();
+    /// let archive = zipper.write_to_vec().unwrap();
+    /// assert_eq!(archive[..4], *b"PK\x03\x04");
+    /// ```
+    pub fn write_to_vec(&mut self) -> Result<Vec<u8>, Error> {
+        // Data, file names and the fixed parts of the local and central headers
+        let capacity = self
+            .data
+            .files
+            .iter()
+            .map(|file| file.data.len() + 2 * file.header.filename.len() + 76)
+            .sum();
+        let mut output = std::io::Cursor::new(Vec::with_capacity(capacity));
+        self.write(&mut output)?;
+        Ok(output.into_inner())
+    }
+
+    /// Write compressed data to a writer that doesn't support seeking, such as a socket or
+    /// [`Stdout`](std::io::Stdout). Otherwise the same as [`write`](Self::write).
+    ///
+    /// Seeking is not needed for most entries because they are compressed before they are
+    /// written, so their size and checksum are known upfront. [Streamed](ZipFileBuilder::streamed)
+    /// files are still compressed directly into the output, their checksum and sizes are written
+    /// after the data in a data descriptor. Stored files are never streamed here, as readers that
+    /// don't use the central directory couldn't find the end of stored data with a data
+    /// descriptor.
+    ///
+    /// ```no_run
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_memory(b"Hello, world!".as_slice(), "hello_world.txt".to_owned())
+    ///     .done();
+    /// zipper.write_stream(std::io::stdout().lock()).unwrap();
+    /// ```
+    #[inline]
+    pub fn write_stream<W: Write>(&mut self, writer: W) -> Result<u64, Error> {
+        for job in &mut self.jobs_queue {
+            job.resolve_compression_type();
+            if job.compression_type == CompressionType::Stored {
+                job.streamed = false;
+            } else {
+                job.data_descriptor = true;
+            }
+        }
+        let mut writer = PositionTrackingWriter::new(writer);
+        let written = self.write_with_threads(&mut writer, self.get_threads())?;
+        writer.flush()?;
+        Ok(written)
+    }
+
+    /// Write the archive split into volumes of at most `max_volume_size` bytes, for media with
+    /// size limits. `open_volume` is called with the index of each volume, starting from 0, to
+    /// create it. Returns the amount of volumes written.
+    ///
+    /// Following Info-ZIP, volumes are usually named `name.z01`, `name.z02` and so on, with the
+    /// last one named `name.zip`. The last volume isn't known until everything is written, so it
+    /// has to be renamed afterwards.
+    ///
+    /// All files are compressed into memory before writing. File data continues in the next
+    /// volume if it doesn't fit, headers are never split. The central directory is always in the
+    /// last volume and can make it larger than `max_volume_size`. Returns
+    /// [`Error::VolumeTooSmall`] if `max_volume_size` is less than 64 KiB.
+    ///
+    /// If the whole archive fits into the first volume, the spanning signature at its start is
+    /// replaced with the `PK00` marker for archives that ended up not being split, which is why
+    /// volumes have to be seekable.
+    ///
+    /// ```no_run
+    /// # use std::fs::File;
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_memory(vec![0; 1024 * 1024], "zeroes.bin".to_owned())
+    ///     .done();
+    ///
+    /// let volumes = zipper
+    ///     .write_split(64 * 1024, |i| File::create(format!("output.z{:02}", i + 1)))
+    ///     .unwrap();
+    /// std::fs::rename(format!("output.z{volumes:02}"), "output.zip").unwrap();
+    /// ```
+    pub fn write_split<W: Write + Seek, F: FnMut(usize) -> std::io::Result<W>>(
+        &mut self,
+        max_volume_size: u64,
+        open_volume: F,
+    ) -> Result<usize, Error> {
+        // Streamed files need seeking, so they are compressed into memory like the rest
+        self.jobs_queue
+            .iter_mut()
+            .for_each(|job| job.streamed = false);
+        self.compress_with_threads(self.get_threads())?;
+        self.data.write_split(max_volume_size, open_volume)
+    }
+
+    /// Write the only file in the archive as a gzip stream instead of a zip archive. Returns
+    /// [`Error::NotSingleFile`] if the archive doesn't contain exactly one file, or if it's a
+    /// directory.
+    ///
+    /// If the file is waiting to be compressed, it's always compressed with
+    /// [deflate](CompressionType::Deflate) and without encryption, other settings of the entry
+    /// are used. Files that are compressed already can't be encrypted. The file stays in the
+    /// archive afterwards, compressed. Files that were stored are compressed with the level set
+    /// for the entry. A file added from the filesystem that can't be opened stays queued, so
+    /// that it can be written once the error is dealt with.
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_memory(b"Hello, world!".as_slice(), "hello_world.txt".to_owned())
+    ///     .done();
+    /// let mut output = Vec::new();
+    /// zipper.write_gzip(&mut output).unwrap();
+    /// assert_eq!(output[..2], [0x1f, 0x8b]);
+    /// ```
+    pub fn write_gzip<W: Write>(&mut self, mut writer: W) -> Result<(), Error> {
+        if self.entry_count() != 1 {
+            return Err(Error::NotSingleFile);
+        }
+        if let Some(job) = self.jobs_queue.last_mut() {
+            // Opened while the job is still queued, so that it isn't lost if the file is missing
+            job.open_fs_source()?;
+        }
+        if let Some(mut job) = self.jobs_queue.pop() {
+            job.compression_type = CompressionType::Deflate;
+            job.fallback_to_stored = false;
+            job.password = None;
+            self.push_file(job.into_file()?);
         }
+        let strip_timestamps = self.data.deterministic || self.data.strip_timestamps;
+        let file = &mut self.data.files[0];
+        if file.header.encrypted || file.header.filename.ends_with('/') {
+            return Err(Error::NotSingleFile);
+        }
+        if strip_timestamps {
+            file.strip_timestamps();
+        }
+        write_gzip_member(file, &mut writer)?;
+        writer.flush()?;
+        Ok(())
     }
 
     /// Starts the compression jobs and passes teh mpsc receiver to teh consumer function, which
     /// might either store the data in [`ZipData`] - [`Self::compress_with_threads`]; or write the
-    /// zip data as soon as it's available - [`Self::write_with_threads`]
-    fn compress_with_consumer<F, T>(&mut self, threads: usize, consumer: F) -> T
+    /// zip data as soon as it's available - [`Self::write_with_threads`]. Workers stop taking new
+    /// jobs once any of the `stop` flags is set.
+    fn compress_with_consumer<F, T>(
+        &mut self,
+        threads: usize,
+        stop: &[&AtomicBool],
+        consumer: F,
+    ) -> T
     where
-        F: FnOnce(&mut ZipData, mpsc::Receiver<ZipFile>) -> T,
+        F: FnOnce(&mut ZipData, LimitedReceiver<'_>) -> T,
     {
-        let jobs_drain = Mutex::new(self.jobs_queue.drain(..));
-        let jobs_drain_ref = &jobs_drain;
-        std::thread::scope(|s| {
+        let limit = BufferLimit::new(self.max_buffered_bytes);
+        let limit_ref = &limit;
+        // Workers beyond one per job would have nothing to do, and without any workers the jobs
+        // would be silently dropped
+        let threads = threads.min(self.jobs_queue.len()).max(1);
+        // In order, the jobs have to be taken front-first from a single queue, otherwise results
+        // would pile up waiting for the earlier ones
+        let queue_count = if self.preserve_order { 1 } else { threads };
+        let pending_jobs = JobQueues::new(std::mem::take(&mut self.jobs_queue), queue_count);
+        let pending_jobs_ref = &pending_jobs;
+        let preserve_order = self.preserve_order;
+        let result = std::thread::scope(|s| {
             let rx = {
-                let (tx, rx) = mpsc::channel();
-                for _ in 0..threads {
+                // Bounded so that the compression doesn't get too far ahead of the consumer,
+                // otherwise all of the compressed data could end up in memory
+                let (tx, rx) = mpsc::sync_channel(threads);
+                for i in 0..threads {
                     let thread_tx = tx.clone();
-                    s.spawn(move || loop {
-                        let next_job = jobs_drain_ref.lock().unwrap().next_back();
-                        if let Some(job) = next_job {
-                            thread_tx.send(job.into_file().unwrap()).unwrap();
-                        } else {
-                            break;
+                    let worker = std::thread::Builder::new().name(format!("mtzip-worker-{i}"));
+                    let spawned = worker.spawn_scoped(s, move || {
+                        // Reused for all files compressed by this thread
+                        let mut buffer = Vec::new();
+                        loop {
+                            if stop.iter().any(|stop| stop.load(Ordering::Relaxed)) {
+                                break;
+                            }
+                            // The receiver is dropped if the consumer stopped early due to an
+                            // error, there is no point in continuing then
+                            if !limit_ref.wait() {
+                                break;
+                            }
+                            if let Some((index, job)) = pending_jobs_ref.pop(i) {
+                                let file = job.into_file_with_buffer(&mut buffer);
+                                if let Ok(file) = &file {
+                                    limit_ref.acquire(file.data.len());
+                                }
+                                if thread_tx.send((index, file)).is_err() {
+                                    break;
+                                }
+                            } else {
+                                break;
+                            }
                         }
                     });
+                    // Same as what `Scope::spawn` does
+                    spawned.expect("failed to spawn thread");
                 }
-                rx
+                LimitedReceiver::new(rx, limit_ref, preserve_order)
             };
             consumer(&mut self.data, rx)
-        })
+        });
+        // Jobs that weren't started because of an error or cancellation stay queued
+        self.jobs_queue.extend(pending_jobs.into_jobs());
+        result
+    }
+
+    /// Take out the jobs that have to be compressed directly into the output
+    fn take_streamed_jobs(&mut self) -> Vec<ZipJob<'d, 'p, 'r>> {
+        let (streamed_jobs, jobs_queue) = std::mem::take(&mut self.jobs_queue)
+            .into_iter()
+            .partition(|job| job.streamed && job.password.is_none());
+        self.jobs_queue = jobs_queue;
+        streamed_jobs
+    }
+
+    /// Normalize the paths if enabled, check that they fit into the headers and check for
+    /// duplicates if enabled. Done before anything is compressed, so that invalid paths are
+    /// reported before any work is done.
+    fn prepare_paths(&mut self) -> Result<(), Error> {
+        if self.normalize_paths {
+            for job in &mut self.jobs_queue {
+                job.archive_path = normalize_separators(&job.archive_path)?;
+            }
+            for file in &mut self.data.files {
+                let normalized = normalize_separators(&file.header.filename)?;
+                if normalized != file.header.filename {
+                    let header = &mut file.header;
+                    let header_name = header
+                        .raw_filename
+                        .as_deref()
+                        .unwrap_or(normalized.as_bytes());
+                    for field in &mut header.extra_fields.values {
+                        if let ExtraField::UnicodePath { .. } = field {
+                            *field =
+                                ExtraField::unicode_path_with_header_name(&normalized, header_name);
+                        }
+                    }
+                    header.filename = normalized;
+                }
+            }
+        }
+        self.check_filename_lengths()?;
+        self.check_comment_lengths()?;
+        self.check_duplicates()?;
+        self.check_path_conflicts()
+    }
+
+    /// Comments are only written in the central directory, after all file data, so they are
+    /// checked upfront instead of failing at the very end
+    fn check_comment_lengths(&self) -> Result<(), Error> {
+        let files_comments = self.data.files.iter().map(|file| &file.header.file_comment);
+        let jobs_comments = self.jobs_queue.iter().map(|job| &job.file_comment);
+        if files_comments
+            .chain(jobs_comments)
+            .flatten()
+            .any(|comment| comment.len() > u16::MAX as usize)
+        {
+            Err(Error::CommentTooLong)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn check_filename_lengths(&self) -> Result<(), Error> {
+        let files_paths = self.data.files.iter().map(|file| &file.header.filename);
+        let jobs_paths = self.jobs_queue.iter().map(|job| &job.archive_path);
+        match files_paths
+            .chain(jobs_paths)
+            .find(|path| path.len() > u16::MAX as usize)
+        {
+            Some(path) => Err(Error::FilenameTooLong(path.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn check_duplicates(&self) -> Result<(), Error> {
+        if !self.reject_duplicates {
+            return Ok(());
+        }
+        let mut paths = HashSet::new();
+        let files_paths = self.data.files.iter().map(|file| &file.header.filename);
+        let jobs_paths = self.jobs_queue.iter().map(|job| &job.archive_path);
+        for path in files_paths.chain(jobs_paths) {
+            if !paths.insert(normalize_archive_path(path)) {
+                return Err(Error::DuplicatePath(path.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that file paths don't end with a slash, and that no path is used both for a file and a
+    /// directory, directly or as a parent directory of another entry
+    fn check_path_conflicts(&self) -> Result<(), Error> {
+        let files = self.data.files.iter().map(|file| {
+            let path = file.header.filename.as_str();
+            (path, path.ends_with(['/', '\\']))
+        });
+        let jobs = self.jobs_queue.iter().map(|job| {
+            let is_dir = matches!(job.data_origin, ZipJobOrigin::Directory);
+            (job.archive_path.as_str(), is_dir)
+        });
+        let mut file_paths = HashSet::new();
+        let mut dir_paths = HashSet::new();
+        for (path, is_dir) in files.chain(jobs) {
+            if !is_dir && path.ends_with(['/', '\\']) {
+                return Err(Error::InvalidPath(path.to_owned()));
+            }
+            let normalized = normalize_archive_path(path);
+            // Parent directories exist implicitly, even if they weren't added
+            for (separator, _) in normalized.match_indices('/') {
+                dir_paths.insert(normalized[..separator].to_owned());
+            }
+            if is_dir {
+                dir_paths.insert(normalized);
+            } else {
+                file_paths.insert(normalized);
+            }
+        }
+        match file_paths.intersection(&dir_paths).next() {
+            Some(path) => Err(Error::PathConflict(path.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn get_threads(&self) -> usize {
+        self.thread_count
+            .or_else(|| std::thread::available_parallelism().ok())
+            .map_or(1, NonZeroUsize::get)
+    }
+}
+
+/// Target of the link if `path` is a symbolic link that can be read
+fn symlink_target(path: &Path) -> Option<Vec<u8>> {
+    if !std::fs::symlink_metadata(path).ok()?.is_symlink() {
+        return None;
+    }
+    std::fs::read_link(path).ok().map(link_target_bytes)
+}
+
+/// Link target as stored in the archive
+fn link_target_bytes(target: PathBuf) -> Vec<u8> {
+    cfg_if! {
+        if #[cfg(unix)] {
+            use std::os::unix::ffi::OsStringExt;
+            target.into_os_string().into_vec()
+        } else {
+            // Link targets in archives use forward slashes
+            target.to_string_lossy().replace('\\', "/").into_bytes()
+        }
+    }
+}
+
+/// Path of the AppleDouble entry of the entry at `path`
+fn apple_double_path(path: &str) -> String {
+    let path = path.trim_end_matches('/');
+    match path.rsplit_once('/') {
+        Some((parent, name)) => format!("__MACOSX/{parent}/._{name}"),
+        None => format!("__MACOSX/._{path}"),
+    }
+}
+
+/// AppleDouble file with the Finder info and an empty resource fork, laid out as macOS writes it
+fn apple_double(finder_info: &[u8; 32]) -> Vec<u8> {
+    const MAGIC: u32 = 0x00051607;
+    const VERSION: u32 = 0x00020000;
+    const FINDER_INFO_ID: u32 = 9;
+    const RESOURCE_FORK_ID: u32 = 2;
+    // Header and two entry descriptors
+    const FINDER_INFO_OFFSET: u32 = 26 + 2 * 12;
+
+    let mut data = Vec::with_capacity(FINDER_INFO_OFFSET as usize + finder_info.len());
+    // All fields are big-endian
+    data.extend_from_slice(&MAGIC.to_be_bytes());
+    data.extend_from_slice(&VERSION.to_be_bytes());
+    // Filler, macOS puts the name of the system there
+    data.extend_from_slice(b"Mac OS X        ");
+    // Number of entries
+    data.extend_from_slice(&2u16.to_be_bytes());
+    for (id, offset, len) in [
+        (FINDER_INFO_ID, FINDER_INFO_OFFSET, finder_info.len() as u32),
+        (
+            RESOURCE_FORK_ID,
+            FINDER_INFO_OFFSET + finder_info.len() as u32,
+            0,
+        ),
+    ] {
+        data.extend_from_slice(&id.to_be_bytes());
+        data.extend_from_slice(&offset.to_be_bytes());
+        data.extend_from_slice(&len.to_be_bytes());
     }
+    data.extend_from_slice(finder_info);
+    data
+}
 
-    fn get_threads() -> usize {
-        std::thread::available_parallelism()
-            .map(NonZeroUsize::get)
-            .unwrap_or(1)
+/// Path with `/` as the separator, as the specification requires, and without leading slashes.
+/// Paths with `..` components are rejected.
+fn normalize_separators(path: &str) -> Result<String, Error> {
+    let normalized = path.replace('\\', "/");
+    let normalized = normalized.trim_start_matches('/');
+    if normalized.split('/').any(|component| component == "..") {
+        return Err(Error::InvalidPath(path.to_owned()));
     }
+    Ok(normalized.to_owned())
+}
+
+/// Path in the archive with forward slashes and without the trailing slash of directories, for
+/// comparing paths
+fn normalize_archive_path(path: &str) -> String {
+    path.replace('\\', "/").trim_end_matches('/').to_owned()
 }
 
 #[cfg(feature = "rayon")]
@@ -448,14 +2719,69 @@ impl<'d, 'p, 'r> ZipArchive<'d, 'p, 'r> {
     /// If you want to limit the amount of threads to be used, use
     /// [`rayon::ThreadPoolBuilder::num_threads`] and either set it as a global pool, or
     /// [`rayon::ThreadPool::install`] the call to this method in it.
-    pub fn compress_with_rayon(&mut self) {
-        if !self.jobs_queue.is_empty() {
-            let files_par_iter = self
-                .jobs_queue
-                .par_drain(..)
-                .map(|job| job.into_file().unwrap());
-            self.data.files.par_extend(files_par_iter)
+    ///
+    /// Returns the first error encountered while reading or compressing the files. Files that
+    /// were compressed before that are kept, and jobs that weren't started stay queued, so that
+    /// compressing can be retried.
+    pub fn compress_with_rayon(&mut self) -> Result<(), Error> {
+        self.prepare_paths()?;
+        if self.jobs_queue.is_empty() {
+            return Ok(());
+        }
+        let slots = self.take_job_slots();
+        let failed = AtomicBool::new(false);
+        let results: Vec<_> = slots
+            .par_iter()
+            .map_init(Vec::new, |buffer, slot| {
+                Self::compress_job_slot(slot, buffer, &failed)
+            })
+            .flatten()
+            .collect();
+        self.requeue_job_slots(slots);
+        let mut first_error = None;
+        for result in results {
+            match result {
+                Ok(file) => self.push_file(file),
+                Err(err) => {
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+        first_error.map_or(Ok(()), |err| Err(err.into()))
+    }
+
+    /// Take the queued jobs out, each into a slot that it is taken from once it is started
+    fn take_job_slots(&mut self) -> Vec<Mutex<Option<ZipJob<'d, 'p, 'r>>>> {
+        std::mem::take(&mut self.jobs_queue)
+            .into_iter()
+            .map(|job| Mutex::new(Some(job)))
+            .collect()
+    }
+
+    /// Put the jobs that weren't started back into the queue, in their original order
+    fn requeue_job_slots(&mut self, slots: Vec<Mutex<Option<ZipJob<'d, 'p, 'r>>>>) {
+        let jobs = slots
+            .into_iter()
+            .filter_map(|slot| slot.into_inner().unwrap());
+        self.jobs_queue.extend(jobs);
+    }
+
+    /// Compress the job in `slot` unless compressing another one failed already, which is
+    /// recorded in `failed`. The job is left in its slot if it isn't started.
+    fn compress_job_slot(
+        slot: &Mutex<Option<ZipJob<'d, 'p, 'r>>>,
+        buffer: &mut Vec<u8>,
+        failed: &AtomicBool,
+    ) -> Option<std::io::Result<ZipFile>> {
+        if failed.load(Ordering::Relaxed) {
+            return None;
+        }
+        let job = slot.lock().unwrap().take()?;
+        let file = job.into_file_with_buffer(buffer);
+        if file.is_err() {
+            failed.store(true, Ordering::Relaxed);
         }
+        Some(file)
     }
 
     /// Write the contents to a writer.
@@ -465,15 +2791,60 @@ impl<'d, 'p, 'r> ZipArchive<'d, 'p, 'r> {
     pub fn write_with_rayon<W: Write + Seek + Send>(
         &mut self,
         writer: &mut W,
-    ) -> std::io::Result<()> {
+    ) -> Result<u64, Error> {
+        self.prepare_paths()?;
+        if self.data.deterministic {
+            // All files have to be compressed before they can be sorted
+            self.compress_with_rayon()?;
+        }
+        let streamed_jobs = self.take_streamed_jobs();
         if !self.jobs_queue.is_empty() {
-            let files_par_iter = self
-                .jobs_queue
-                .par_drain(..)
-                .map(|job| job.into_file().unwrap());
-            self.data.write_rayon(writer, files_par_iter)
+            // Jobs that weren't started when compressing or writing failed stay queued
+            let slots = self.take_job_slots();
+            let failed = AtomicBool::new(false);
+            let files_par_iter = slots
+                .par_iter()
+                .map_init(Vec::new, |buffer, slot| {
+                    Self::compress_job_slot(slot, buffer, &failed)
+                })
+                .flatten();
+            let result = self.data.write_rayon(writer, files_par_iter, streamed_jobs);
+            self.requeue_job_slots(slots);
+            result
         } else {
-            self.data.write_rayon(writer, rayon::iter::empty())
+            self.data
+                .write_rayon(writer, rayon::iter::empty(), streamed_jobs)
         }
     }
+
+    /// Compress contents using the specified rayon thread pool. Useful for avoiding
+    /// oversubscription when the pool is shared with other work.
+    ///
+    /// Same as [installing](rayon::ThreadPool::install) [`Self::compress_with_rayon`] in the pool.
+    ///
+    /// ```
+    /// # use mtzip::ZipArchive;
+    /// let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+    /// let mut zipper = ZipArchive::new();
+    /// zipper
+    ///     .add_file_from_memory(b"Hello, world!".as_slice(), "hello_world.txt".to_owned())
+    ///     .done();
+    /// zipper.compress_with_rayon_in_pool(&pool).unwrap();
+    /// ```
+    #[inline]
+    pub fn compress_with_rayon_in_pool(&mut self, pool: &rayon::ThreadPool) -> Result<(), Error> {
+        pool.install(|| self.compress_with_rayon())
+    }
+
+    /// Write the contents to a writer, using the specified rayon thread pool.
+    ///
+    /// Same as [installing](rayon::ThreadPool::install) [`Self::write_with_rayon`] in the pool.
+    #[inline]
+    pub fn write_with_rayon_in_pool<W: Write + Seek + Send>(
+        &mut self,
+        writer: &mut W,
+        pool: &rayon::ThreadPool,
+    ) -> Result<u64, Error> {
+        pool.install(|| self.write_with_rayon(writer))
+    }
 }
@@ -5,132 +5,398 @@ use std::sync::Mutex;
 #[cfg(feature = "rayon")]
 use rayon::prelude::*;
 
-use super::file::{ZipFile, ZipFileNoData};
+use super::{
+    file::{version_made_by, ZipFile, ZipFileNoData, ZIP64_VERSION_NEEDED_TO_EXTRACT},
+    job::ZipJob,
+    span::SpanningWriter,
+    stream::{PositionTrackingBufWriter, PositionTrackingWriter},
+};
+use crate::{CompressionType, Error, HostOs};
 
-const END_OF_CENTRAL_DIR_SIGNATURE: u32 = 0x06054B50;
+pub(crate) const END_OF_CENTRAL_DIR_SIGNATURE: u32 = 0x06054B50;
+pub(crate) const ZIP64_END_OF_CENTRAL_DIR_SIGNATURE: u32 = 0x06064B50;
+pub(crate) const ZIP64_END_OF_CENTRAL_DIR_LOCATOR_SIGNATURE: u32 = 0x07064B50;
+
+/// Central directory entries, encoded as soon as their files are written. Only the encoded
+/// entries are kept until the central directory is written, instead of the headers of all files.
+#[derive(Debug, Default)]
+pub struct CentralDirectory {
+    entries: Vec<u8>,
+    len: usize,
+}
+
+impl CentralDirectory {
+    fn push(&mut self, zip_file: ZipFileNoData, host_os: HostOs) -> Result<(), Error> {
+        zip_file.write_central_directory_entry(&mut self.entries, host_os)?;
+        self.len += 1;
+        Ok(())
+    }
+}
+
+/// Where the central directory is, as written in the end of central directory records
+#[derive(Debug, Clone, Copy)]
+struct CentralDirectoryLocation {
+    offset: u64,
+    size: u64,
+    /// Entries in the part of the central directory on the last disk
+    entries_this_disk: u64,
+    total_entries: u64,
+    /// Last disk, which has the end of central directory records
+    disk_number: u32,
+    /// Disk that the central directory starts on
+    disk_with_central_dir: u32,
+}
+
+impl CentralDirectoryLocation {
+    /// Central directory that is written as a whole on the last disk
+    fn single_disk(offset: u64, end: u64, entries: usize, disk_number: u32) -> Self {
+        Self {
+            offset,
+            size: end - offset,
+            entries_this_disk: entries as u64,
+            total_entries: entries as u64,
+            disk_number,
+            disk_with_central_dir: disk_number,
+        }
+    }
+}
 
 #[derive(Debug, Default)]
 pub struct ZipData {
     pub files: Vec<ZipFile>,
+    pub archive_comment: Option<String>,
+    /// Sort the files and strip timestamps before writing
+    pub deterministic: bool,
+    /// Strip timestamps before writing
+    pub strip_timestamps: bool,
+    /// Alignment for stored files that don't have one set
+    pub stored_alignment: Option<u16>,
+    pub host_os: HostOs,
 }
 
 impl ZipData {
-    pub fn write<W: Write + Seek, I: IntoIterator<Item = ZipFile>>(
+    pub fn write<W: Write + Seek, I: IntoIterator<Item = std::io::Result<ZipFile>>>(
         &mut self,
         buf: &mut W,
         zip_file_iter: I,
-    ) -> std::io::Result<()> {
-        let zip_files = self.write_files_contained_and_iter(buf, zip_file_iter)?;
+        streamed_jobs: Vec<ZipJob>,
+    ) -> Result<u64, Error> {
+        // Headers are written in many small pieces
+        let buf = &mut PositionTrackingBufWriter::new(buf)?;
+        let start = buf.stream_position()?;
+
+        let mut central_dir = CentralDirectory::default();
+        self.write_files_contained_and_iter(buf, zip_file_iter, &mut central_dir)?;
+        self.write_streamed_jobs(buf, streamed_jobs, &mut central_dir)?;
 
-        let files_amount = super::files_amount_u16(&zip_files);
+        let files_amount = central_dir.len;
 
-        let central_dir_offset = super::stream_position_u32(buf)?;
+        let central_dir_offset = buf.stream_position()?;
 
-        self.write_central_dir(zip_files, buf)?;
+        buf.write_all(&central_dir.entries)?;
 
-        let central_dir_start = super::stream_position_u32(buf)?;
+        let central_dir_end = buf.stream_position()?;
 
         self.write_end_of_central_directory(
             buf,
-            central_dir_offset,
-            central_dir_start,
-            files_amount,
-        )
+            CentralDirectoryLocation::single_disk(
+                central_dir_offset,
+                central_dir_end,
+                files_amount,
+                0,
+            ),
+        )?;
+
+        buf.flush()?;
+
+        Ok(buf.stream_position()? - start)
     }
 
     #[cfg(feature = "rayon")]
-    pub fn write_rayon<W: Write + Seek + Send, I: ParallelIterator<Item = ZipFile>>(
+    pub fn write_rayon<
+        W: Write + Seek + Send,
+        I: ParallelIterator<Item = std::io::Result<ZipFile>>,
+    >(
         &mut self,
         buf: &mut W,
         zip_file_iter: I,
-    ) -> std::io::Result<()> {
-        let zip_files = self.write_files_contained_and_par_iter(buf, zip_file_iter)?;
+        streamed_jobs: Vec<ZipJob>,
+    ) -> Result<u64, Error> {
+        // Headers are written in many small pieces
+        let buf = &mut PositionTrackingBufWriter::new(buf)?;
+        let start = buf.stream_position()?;
 
-        let files_amount = super::files_amount_u16(&zip_files);
+        let mut central_dir = CentralDirectory::default();
+        self.write_files_contained_and_par_iter(buf, zip_file_iter, &mut central_dir)?;
+        self.write_streamed_jobs(buf, streamed_jobs, &mut central_dir)?;
 
-        let central_dir_offset = super::stream_position_u32(buf)?;
+        let files_amount = central_dir.len;
 
-        self.write_central_dir(zip_files, buf)?;
+        let central_dir_offset = buf.stream_position()?;
 
-        let central_dir_start = super::stream_position_u32(buf)?;
+        buf.write_all(&central_dir.entries)?;
+
+        let central_dir_end = buf.stream_position()?;
 
         self.write_end_of_central_directory(
             buf,
-            central_dir_offset,
-            central_dir_start,
-            files_amount,
-        )
+            CentralDirectoryLocation::single_disk(
+                central_dir_offset,
+                central_dir_end,
+                files_amount,
+                0,
+            ),
+        )?;
+
+        buf.flush()?;
+
+        Ok(buf.stream_position()? - start)
+    }
+
+    /// Write the contained files as a split archive, returning the amount of volumes. Files are
+    /// split between volumes, headers are not. The central directory is written into the last
+    /// volume as a whole, which can make it exceed the volume size.
+    pub fn write_split<W: Write + Seek, F: FnMut(usize) -> std::io::Result<W>>(
+        &mut self,
+        max_volume_size: u64,
+        open_volume: F,
+    ) -> Result<usize, Error> {
+        let mut buf = SpanningWriter::new(max_volume_size, open_volume)?;
+        // Assembled in memory first, so that it can be moved to the next volume as a whole if
+        // it doesn't fit
+        let mut central_dir = CentralDirectory::default();
+        let strip_timestamps = self.strips_timestamps();
+        for mut zipfile in self.take_files() {
+            zipfile.apply_default_alignment(self.stored_alignment);
+            if strip_timestamps {
+                zipfile.strip_timestamps();
+            }
+            buf.reserve(zipfile.local_header_max_len())?;
+            let disk_number_start =
+                u16::try_from(buf.disk_number()).map_err(|_| Error::ArchiveTooLarge)?;
+            let mut zipfile = zipfile.write_local_file_header_with_data_consuming(&mut buf)?;
+            zipfile.disk_number_start = disk_number_start;
+            central_dir.push(zipfile, self.host_os)?;
+        }
+
+        let files_amount = central_dir.len;
+        let central_dir = central_dir.entries;
+        let comment_len = self.archive_comment.as_deref().unwrap_or_default().len();
+        buf.reserve(
+            (central_dir.len()
+                + Self::ZIP64_FOOTER_LENGTH
+                + Self::ZIP64_LOCATOR_LENGTH
+                + Self::FOOTER_LENGTH
+                + comment_len) as u64,
+        )?;
+
+        let central_dir_offset = buf.stream_position()?;
+        let central_dir_end = central_dir_offset + central_dir.len() as u64;
+        let mut tail = PositionTrackingWriter::starting_at(central_dir, central_dir_end);
+
+        self.write_end_of_central_directory(
+            &mut tail,
+            CentralDirectoryLocation::single_disk(
+                central_dir_offset,
+                central_dir_end,
+                files_amount,
+                buf.disk_number(),
+            ),
+        )?;
+
+        buf.write_unsplit(&tail.into_inner())?;
+
+        Ok(buf.finish()?)
     }
 
     #[inline]
-    fn write_files_contained_and_iter<W: Write + Seek, I: IntoIterator<Item = ZipFile>>(
+    fn write_files_contained_and_iter<
+        W: Write + Seek,
+        I: IntoIterator<Item = std::io::Result<ZipFile>>,
+    >(
         &mut self,
         buf: &mut W,
         zip_files_iter: I,
-    ) -> std::io::Result<Vec<ZipFileNoData>> {
-        let zip_files = std::mem::take(&mut self.files);
-        self.write_files_iter(buf, zip_files.into_iter().chain(zip_files_iter))
+        central_dir: &mut CentralDirectory,
+    ) -> std::io::Result<()> {
+        let zip_files = self.take_files();
+        self.write_files_iter(
+            buf,
+            zip_files.into_iter().map(Ok).chain(zip_files_iter),
+            central_dir,
+        )
     }
 
     #[cfg(feature = "rayon")]
     #[inline]
     pub fn write_files_contained_and_par_iter<
         W: Write + Seek + Send,
-        I: ParallelIterator<Item = ZipFile>,
+        I: ParallelIterator<Item = std::io::Result<ZipFile>>,
     >(
         &mut self,
         buf: &mut W,
         zip_files_iter: I,
-    ) -> std::io::Result<Vec<ZipFileNoData>> {
-        let zip_files = std::mem::take(&mut self.files);
-        self.write_files_par_iter(buf, zip_files.into_par_iter().chain(zip_files_iter))
+        central_dir: &mut CentralDirectory,
+    ) -> std::io::Result<()> {
+        let zip_files = self.take_files();
+        self.write_files_par_iter(
+            buf,
+            zip_files.into_par_iter().map(Ok).chain(zip_files_iter),
+            central_dir,
+        )
+    }
+
+    /// Take the contained files out for writing, applying deterministic ordering if enabled
+    fn take_files(&mut self) -> Vec<ZipFile> {
+        let mut zip_files = std::mem::take(&mut self.files);
+        if self.deterministic {
+            zip_files.sort_by(|a, b| a.header.filename.cmp(&b.header.filename));
+        }
+        zip_files
+    }
+
+    /// Size of the archive if it was written with `jobs` queued. Exact as long as there are no
+    /// jobs, see [`ZipJob::estimated_len`] for how they are estimated.
+    pub fn estimated_size(&self, jobs: &[ZipJob]) -> u64 {
+        let mut files: Vec<_> = self.files.iter().collect();
+        if self.deterministic {
+            files.sort_by(|a, b| a.header.filename.cmp(&b.header.filename));
+        }
+        let mut central_dir_offset = 0;
+        let mut central_dir_size = 0;
+        for file in files {
+            let (local_len, central_len) = file.written_len(
+                central_dir_offset,
+                self.stored_alignment,
+                self.strips_timestamps(),
+            );
+            central_dir_offset += local_len;
+            central_dir_size += central_len;
+        }
+        for job in jobs {
+            let (local_len, central_len) = job.estimated_len();
+            central_dir_offset += local_len;
+            central_dir_size += central_len;
+        }
+
+        let needs_zip64 = self.files.len() + jobs.len() >= u16::MAX.into()
+            || central_dir_offset >= u32::MAX.into()
+            || central_dir_size >= u32::MAX.into();
+        let zip64_len = if needs_zip64 {
+            Self::ZIP64_FOOTER_LENGTH + Self::ZIP64_LOCATOR_LENGTH
+        } else {
+            0
+        };
+        let comment_len = self.archive_comment.as_ref().map_or(0, String::len);
+        central_dir_offset
+            + central_dir_size
+            + (zip64_len + Self::FOOTER_LENGTH + comment_len) as u64
+    }
+
+    #[inline]
+    fn strips_timestamps(&self) -> bool {
+        self.deterministic || self.strip_timestamps
     }
 
-    pub fn write_files_iter<W: Write + Seek, I: IntoIterator<Item = ZipFile>>(
+    pub fn write_files_iter<W: Write + Seek, I: IntoIterator<Item = std::io::Result<ZipFile>>>(
         &mut self,
         buf: &mut W,
         zip_files: I,
-    ) -> std::io::Result<Vec<ZipFileNoData>> {
-        zip_files
-            .into_iter()
-            .map(|zipfile| zipfile.write_local_file_header_with_data_consuming(buf))
-            .collect::<std::io::Result<Vec<_>>>()
+        central_dir: &mut CentralDirectory,
+    ) -> std::io::Result<()> {
+        let strip_timestamps = self.strips_timestamps();
+        zip_files.into_iter().try_for_each(|zipfile| {
+            let mut zipfile = zipfile?;
+            zipfile.apply_default_alignment(self.stored_alignment);
+            if strip_timestamps {
+                zipfile.strip_timestamps();
+            }
+            let zipfile = zipfile.write_local_file_header_with_data_consuming(buf)?;
+            Ok(central_dir.push(zipfile, self.host_os)?)
+        })
     }
 
     #[cfg(feature = "rayon")]
-    pub fn write_files_par_iter<W: Write + Seek + Send, I: ParallelIterator<Item = ZipFile>>(
+    pub fn write_files_par_iter<
+        W: Write + Seek + Send,
+        I: ParallelIterator<Item = std::io::Result<ZipFile>>,
+    >(
         &mut self,
         buf: &mut W,
         zip_files: I,
-    ) -> std::io::Result<Vec<ZipFileNoData>> {
-        let buf = Mutex::new(buf);
-        zip_files
-            .map(|zipfile| {
-                let mut buf_lock = buf.lock().unwrap();
-                zipfile.write_local_file_header_with_data_consuming(*buf_lock)
-            })
-            .collect::<std::io::Result<Vec<_>>>()
+        central_dir: &mut CentralDirectory,
+    ) -> std::io::Result<()> {
+        let output = Mutex::new((buf, central_dir));
+        let stored_alignment = self.stored_alignment;
+        let strip_timestamps = self.strips_timestamps();
+        let host_os = self.host_os;
+        zip_files.try_for_each(|zipfile| {
+            let mut zipfile = zipfile?;
+            zipfile.apply_default_alignment(stored_alignment);
+            if strip_timestamps {
+                zipfile.strip_timestamps();
+            }
+            let mut output_lock = output.lock().unwrap();
+            let (buf, central_dir) = &mut *output_lock;
+            let zipfile = zipfile.write_local_file_header_with_data_consuming(*buf)?;
+            Ok(central_dir.push(zipfile, host_os)?)
+        })
     }
 
-    fn write_central_dir<W: Write, I: IntoIterator<Item = ZipFileNoData>>(
+    /// Compress the jobs one by one directly into the writer
+    fn write_streamed_jobs<W: Write + Seek>(
         &self,
-        zip_files: I,
         buf: &mut W,
+        streamed_jobs: Vec<ZipJob>,
+        central_dir: &mut CentralDirectory,
     ) -> std::io::Result<()> {
-        zip_files
-            .into_iter()
-            .try_for_each(|zip_file| zip_file.write_central_directory_entry(buf))
+        streamed_jobs.into_iter().try_for_each(|mut job| {
+            job.resolve_compression_type();
+            if job.alignment.is_none() && job.compression_type == CompressionType::Stored {
+                job.alignment = self.stored_alignment;
+            }
+            job.strip_timestamps = self.strips_timestamps();
+            let zipfile = job.write_streamed(buf)?;
+            Ok(central_dir.push(zipfile, self.host_os)?)
+        })
     }
 
     const FOOTER_LENGTH: usize = 22;
+    const ZIP64_FOOTER_LENGTH: usize = 56;
+    const ZIP64_LOCATOR_LENGTH: usize = 20;
 
-    fn write_end_of_central_directory<W: Write>(
+    fn write_end_of_central_directory<W: Write + Seek>(
         &self,
         buf: &mut W,
-        central_dir_offset: u32,
-        central_dir_start: u32,
-        files_amount: u16,
+        location: CentralDirectoryLocation,
     ) -> std::io::Result<()> {
+        let needs_zip64 = location.total_entries >= u16::MAX.into()
+            || location.offset >= u32::MAX.into()
+            || location.size >= u32::MAX.into()
+            || location.disk_number >= u16::MAX.into();
+
+        if needs_zip64 {
+            self.write_zip64_end_of_central_directory(buf, location)?;
+        }
+
+        // Values that don't fit are replaced with sentinels, the real values are in the ZIP64
+        // record
+        let entries_this_disk = u16::try_from(location.entries_this_disk).unwrap_or(u16::MAX);
+        let total_entries = u16::try_from(location.total_entries).unwrap_or(u16::MAX);
+        let central_dir_size = u32::try_from(location.size).unwrap_or(u32::MAX);
+        let central_dir_offset = u32::try_from(location.offset).unwrap_or(u32::MAX);
+        let disk_number = u16::try_from(location.disk_number).unwrap_or(u16::MAX);
+        let disk_with_central_dir =
+            u16::try_from(location.disk_with_central_dir).unwrap_or(u16::MAX);
+
+        let archive_comment = self
+            .archive_comment
+            .as_deref()
+            .unwrap_or_default()
+            .as_bytes();
+        let archive_comment_len =
+            u16::try_from(archive_comment.len()).map_err(|_| Error::CommentTooLong)?;
+
         // Temporary in-memory statically sized array
         let mut central_dir = [0; Self::FOOTER_LENGTH];
         {
@@ -139,23 +405,85 @@ impl ZipData {
             // Signature
             central_dir_buf.write_all(&END_OF_CENTRAL_DIR_SIGNATURE.to_le_bytes())?;
             // number of this disk
-            central_dir_buf.write_all(&0_u16.to_le_bytes())?;
+            central_dir_buf.write_all(&disk_number.to_le_bytes())?;
             // number of the disk with start
-            central_dir_buf.write_all(&0_u16.to_le_bytes())?;
+            central_dir_buf.write_all(&disk_with_central_dir.to_le_bytes())?;
             // Number of entries on this disk
-            central_dir_buf.write_all(&files_amount.to_le_bytes())?;
+            central_dir_buf.write_all(&entries_this_disk.to_le_bytes())?;
             // Number of entries
-            central_dir_buf.write_all(&files_amount.to_le_bytes())?;
+            central_dir_buf.write_all(&total_entries.to_le_bytes())?;
             // Central dir size
-            central_dir_buf.write_all(&(central_dir_start - central_dir_offset).to_le_bytes())?;
+            central_dir_buf.write_all(&central_dir_size.to_le_bytes())?;
             // Central dir offset
             central_dir_buf.write_all(&central_dir_offset.to_le_bytes())?;
             // Comment length
-            central_dir_buf.write_all(&0_u16.to_le_bytes())?;
+            central_dir_buf.write_all(&archive_comment_len.to_le_bytes())?;
         }
 
         buf.write_all(&central_dir)?;
 
+        // Comment
+        buf.write_all(archive_comment)?;
+
+        Ok(())
+    }
+
+    /// Writes the ZIP64 end of central directory record followed by its locator. Must be written
+    /// right before the regular end of central directory record.
+    fn write_zip64_end_of_central_directory<W: Write + Seek>(
+        &self,
+        buf: &mut W,
+        location: CentralDirectoryLocation,
+    ) -> std::io::Result<()> {
+        let zip64_end_of_central_dir_offset = buf.stream_position()?;
+
+        // Temporary in-memory statically sized array
+        let mut zip64_central_dir = [0; Self::ZIP64_FOOTER_LENGTH];
+        {
+            let mut zip64_central_dir_buf: &mut [u8] = &mut zip64_central_dir;
+
+            // Signature
+            zip64_central_dir_buf.write_all(&ZIP64_END_OF_CENTRAL_DIR_SIGNATURE.to_le_bytes())?;
+            // Size of the record, not counting the signature and this field
+            zip64_central_dir_buf
+                .write_all(&((Self::ZIP64_FOOTER_LENGTH - 12) as u64).to_le_bytes())?;
+            // version made by
+            zip64_central_dir_buf.write_all(&version_made_by(self.host_os).to_le_bytes())?;
+            // version needed to extract
+            zip64_central_dir_buf.write_all(&ZIP64_VERSION_NEEDED_TO_EXTRACT.to_le_bytes())?;
+            // number of this disk
+            zip64_central_dir_buf.write_all(&location.disk_number.to_le_bytes())?;
+            // number of the disk with start
+            zip64_central_dir_buf.write_all(&location.disk_with_central_dir.to_le_bytes())?;
+            // Number of entries on this disk
+            zip64_central_dir_buf.write_all(&location.entries_this_disk.to_le_bytes())?;
+            // Number of entries
+            zip64_central_dir_buf.write_all(&location.total_entries.to_le_bytes())?;
+            // Central dir size
+            zip64_central_dir_buf.write_all(&location.size.to_le_bytes())?;
+            // Central dir offset
+            zip64_central_dir_buf.write_all(&location.offset.to_le_bytes())?;
+        }
+
+        buf.write_all(&zip64_central_dir)?;
+
+        // Temporary in-memory statically sized array
+        let mut locator = [0; Self::ZIP64_LOCATOR_LENGTH];
+        {
+            let mut locator_buf: &mut [u8] = &mut locator;
+
+            // Signature
+            locator_buf.write_all(&ZIP64_END_OF_CENTRAL_DIR_LOCATOR_SIGNATURE.to_le_bytes())?;
+            // number of the disk with the start of the zip64 end of central directory
+            locator_buf.write_all(&location.disk_number.to_le_bytes())?;
+            // Offset of the zip64 end of central directory record
+            locator_buf.write_all(&zip64_end_of_central_dir_offset.to_le_bytes())?;
+            // Total number of disks
+            locator_buf.write_all(&(location.disk_number + 1).to_le_bytes())?;
+        }
+
+        buf.write_all(&locator)?;
+
         Ok(())
     }
 }
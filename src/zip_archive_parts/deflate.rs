@@ -0,0 +1,84 @@
+use std::io::{Read, Write};
+
+use cfg_if::cfg_if;
+
+use crate::{level::CompressionLevel, DeflateStrategy};
+
+cfg_if! {
+    if #[cfg(feature = "rust_backend")] {
+        use std::io::ErrorKind;
+
+        use miniz_oxide::deflate::core::{
+            compress, create_comp_flags_from_zip_params, CompressorOxide, TDEFLFlush, TDEFLStatus,
+        };
+
+        /// Negative window bits make the compressor produce raw deflate data, without a zlib
+        /// header
+        const RAW_DEFLATE_WINDOW_BITS: i32 = -15;
+        const BUFFER_LEN: usize = 64 * 1024;
+
+        /// Compress `source` into raw deflate data with the given strategy, which flate2 doesn't
+        /// allow to choose. Returns the amount of bytes read from `source`.
+        pub fn deflate_with_strategy<R: Read, W: Write + ?Sized>(
+            mut source: R,
+            output: &mut W,
+            compression_level: CompressionLevel,
+            strategy: DeflateStrategy,
+        ) -> std::io::Result<u64> {
+            let flags = create_comp_flags_from_zip_params(
+                compression_level.get().into(),
+                RAW_DEFLATE_WINDOW_BITS,
+                strategy as i32,
+            );
+            let mut compressor = CompressorOxide::new(flags);
+            let mut input_buf = vec![0; BUFFER_LEN];
+            let mut output_buf = vec![0; BUFFER_LEN];
+            let mut total_in = 0;
+
+            loop {
+                let read = match source.read(&mut input_buf) {
+                    Ok(read) => read,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                };
+                total_in += read as u64;
+                let flush = if read == 0 {
+                    TDEFLFlush::Finish
+                } else {
+                    TDEFLFlush::None
+                };
+
+                let mut input = &input_buf[..read];
+                loop {
+                    let (status, consumed, written) =
+                        compress(&mut compressor, input, &mut output_buf, flush);
+                    output.write_all(&output_buf[..written])?;
+                    input = &input[consumed..];
+                    match status {
+                        TDEFLStatus::Done => return Ok(total_in),
+                        TDEFLStatus::Okay if flush == TDEFLFlush::None && input.is_empty() => break,
+                        TDEFLStatus::Okay => {}
+                        TDEFLStatus::BadParam | TDEFLStatus::PutBufFailed => {
+                            return Err(std::io::Error::other("deflate compression failed"))
+                        }
+                    }
+                }
+            }
+        }
+    } else {
+        use flate2::read::DeflateEncoder;
+
+        /// Compress `source` into raw deflate data. The strategy is ignored, only the pure Rust
+        /// backend allows to choose it. Returns the amount of bytes read from `source`.
+        pub fn deflate_with_strategy<R: Read, W: Write + ?Sized>(
+            source: R,
+            output: &mut W,
+            compression_level: CompressionLevel,
+            _strategy: DeflateStrategy,
+        ) -> std::io::Result<u64> {
+            let mut encoder = DeflateEncoder::new(source, compression_level.into());
+            std::io::copy(&mut encoder, output)?;
+            Ok(encoder.total_in())
+        }
+    }
+}
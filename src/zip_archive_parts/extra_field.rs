@@ -1,8 +1,15 @@
 //! ZIP file extra field
 
-use std::{fs::Metadata, io::Write};
+use std::{
+    fs::Metadata,
+    io::Write,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use cfg_if::cfg_if;
+use flate2::Crc;
+
+use super::job::system_time_to_secs;
 
 /// This is a structure containing [`ExtraField`]s associated with a file or directory in a zip
 /// file, mostly used for filesystem properties, and this is the only functionality implemented
@@ -45,6 +52,66 @@ impl ExtraFields {
         }
     }
 
+    /// Add an [`ExtraField`].
+    pub fn push(&mut self, field: ExtraField) {
+        self.values.push(field)
+    }
+
+    /// Add an [`ExtraField::Ntfs`] with the specified timestamps.
+    ///
+    /// ```
+    /// # use mtzip::ExtraFields;
+    /// let extra_fields = ExtraFields::default().with_ntfs(133000000000000000, 0, 0);
+    /// ```
+    pub fn with_ntfs(mut self, mtime: u64, atime: u64, ctime: u64) -> Self {
+        self.push(ExtraField::Ntfs {
+            mtime,
+            atime,
+            ctime,
+        });
+        self
+    }
+
+    /// Add an [`ExtraField::UnixExtendedTimestamp`] with the specified timestamps.
+    ///
+    /// ```
+    /// # use mtzip::ExtraFields;
+    /// let extra_fields = ExtraFields::default().with_unix_timestamp(Some(1700000000), None, None);
+    /// ```
+    pub fn with_unix_timestamp(
+        mut self,
+        mod_time: Option<i32>,
+        ac_time: Option<i32>,
+        cr_time: Option<i32>,
+    ) -> Self {
+        self.push(ExtraField::UnixExtendedTimestamp {
+            mod_time,
+            ac_time,
+            cr_time,
+        });
+        self
+    }
+
+    /// Create a set with a single [`ExtraField::UnixExtendedTimestamp`] that only has the
+    /// modification time, without access and creation times that would make the output less
+    /// reproducible.
+    ///
+    /// ```
+    /// # use mtzip::{ExtraField, ExtraFields};
+    /// let extra_fields = ExtraFields::with_mtime(1700000000);
+    /// assert_eq!(
+    ///     extra_fields.into_iter().collect::<Vec<_>>(),
+    ///     [ExtraField::UnixExtendedTimestamp {
+    ///         mod_time: Some(1700000000),
+    ///         ac_time: None,
+    ///         cr_time: None,
+    ///     }]
+    /// );
+    /// ```
+    pub fn with_mtime(secs: i32) -> Self {
+        Self::default().with_unix_timestamp(Some(secs), None, None)
+    }
+
     /// This method will use the filesystem metadata to get the properties that can be stored in
     /// ZIP [`ExtraFields`].
     ///
@@ -70,9 +137,9 @@ impl ExtraFields {
     fn new_linux(metadata: &Metadata) -> Self {
         use std::os::linux::fs::MetadataExt;
 
-        let mod_time = Some(metadata.st_mtime() as i32);
-        let ac_time = Some(metadata.st_atime() as i32);
-        let cr_time = Some(metadata.st_ctime() as i32);
+        let mod_time = metadata.st_mtime().try_into().ok();
+        let ac_time = metadata.st_atime().try_into().ok();
+        let cr_time = Self::creation_time(metadata);
 
         let uid = metadata.st_uid();
         let gid = metadata.st_gid();
@@ -94,9 +161,9 @@ impl ExtraFields {
     fn new_unix(metadata: &Metadata) -> Self {
         use std::os::unix::fs::MetadataExt;
 
-        let mod_time = Some(metadata.mtime() as i32);
-        let ac_time = Some(metadata.atime() as i32);
-        let cr_time = Some(metadata.ctime() as i32);
+        let mod_time = metadata.mtime().try_into().ok();
+        let ac_time = metadata.atime().try_into().ok();
+        let cr_time = Self::creation_time(metadata);
 
         let uid = metadata.uid();
         let gid = metadata.gid();
@@ -113,6 +180,14 @@ impl ExtraFields {
         }
     }
 
+    /// Birth time of the file, if the platform and the filesystem support it. On Linux it is
+    /// queried with `statx`. `ctime` is the time of the last inode change, not the creation time,
+    /// so it's not used in its place.
+    #[cfg(unix)]
+    fn creation_time(metadata: &Metadata) -> Option<i32> {
+        metadata.created().ok().and_then(unix_timestamp)
+    }
+
     #[cfg(all(target_os = "wasi", feature = "wasi_fs"))]
     fn new_wasi(metadata: &Metadata) -> Self {
         use std::os::wasi::fs::MetadataExt;
@@ -147,9 +222,42 @@ impl ExtraFields {
         }
     }
 
-    pub(crate) fn data_length<const CENTRAL_HEADER: bool>(&self) -> u16 {
+    /// Parse extra fields from the extra field data of a local file header. Fields that are not
+    /// supported by [`ExtraField`] or have an unexpected format are kept as
+    /// [`ExtraField::Raw`], except for the ZIP64 extended information field, which is skipped.
+    pub(crate) fn parse(mut data: &[u8]) -> Self {
+        let mut values = Vec::new();
+        while data.len() >= 4 {
+            let header_id = u16::from_le_bytes([data[0], data[1]]);
+            let field_size = u16::from_le_bytes([data[2], data[3]]) as usize;
+            let Some(field_data) = data.get(4..4 + field_size) else {
+                break;
+            };
+            if header_id != ZIP64_EXTRA_FIELD_ID {
+                values.push(ExtraField::parse(header_id, field_data).unwrap_or_else(|| {
+                    ExtraField::Raw {
+                        header_id,
+                        data: field_data.to_vec(),
+                    }
+                }));
+            }
+            data = &data[4 + field_size..];
+        }
+        Self { values }
+    }
+
+    pub(crate) fn data_length<const CENTRAL_HEADER: bool>(&self) -> usize {
+        self.data_length_of::<CENTRAL_HEADER>(|_| true)
+    }
+
+    /// Length of the fields for which `include` returns `true`
+    pub(crate) fn data_length_of<const CENTRAL_HEADER: bool>(
+        &self,
+        include: impl Fn(&ExtraField) -> bool,
+    ) -> usize {
         self.values
             .iter()
+            .filter(|f| include(f))
             .map(|f| 4 + f.field_size::<CENTRAL_HEADER>())
             .sum()
     }
@@ -167,8 +275,9 @@ impl ExtraFields {
 
 /// Extra data that can be associated with a file or directory.
 ///
-/// This library only implements the filesystem properties in NTFS and UNIX format.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// This library only implements the filesystem properties in NTFS and UNIX format, and the
+/// Unicode Path field. Other fields can be added with [`Raw`](Self::Raw).
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ExtraField {
     /// NTFS file properties.
     Ntfs {
@@ -179,8 +288,9 @@ pub enum ExtraField {
         /// File/directory creation timestamp
         ctime: u64,
     },
-    /// Info-Zip extended unix timestamp. Each part is optional by definition, but will be
-    /// populated by [`ExtraFields::new_from_fs`].
+    /// Info-Zip extended unix timestamp. Each part is optional by definition.
+    /// [`ExtraFields::new_from_fs`] populates the modification and access times, and the creation
+    /// time if the filesystem records it.
     UnixExtendedTimestamp {
         /// Last modification timestamp
         mod_time: Option<i32>,
@@ -196,6 +306,47 @@ pub enum ExtraField {
         /// GID of the group
         gid: u32,
     },
+    /// Info-Zip Unicode Path extra field. Contains the UTF-8 file name for extractors that don't
+    /// support the UTF-8 flag. Use [`ExtraField::unicode_path`] to construct it.
+    UnicodePath {
+        /// CRC32 of the file name in the header
+        name_crc: u32,
+        /// File name in UTF-8
+        utf8_name: String,
+    },
+    /// Any other extra field, written as-is in both local and central headers.
+    Raw {
+        /// Header ID of the field
+        header_id: u16,
+        /// Field data, without the header ID and size
+        data: Vec<u8>,
+    },
+}
+
+/// ZIP64 extended information extra field, with the values that don't fit into the headers
+pub(crate) const ZIP64_EXTRA_FIELD_ID: u16 = 0x0001;
+
+/// Time between the NTFS epoch, 1601-01-01, and the UNIX epoch
+const NTFS_EPOCH_OFFSET: Duration = Duration::from_secs(11_644_473_600);
+
+/// Seconds since UNIX epoch, if the time fits into a [`UnixExtendedTimestamp`] field
+///
+/// [`UnixExtendedTimestamp`]: ExtraField::UnixExtendedTimestamp
+#[inline]
+fn unix_timestamp(time: SystemTime) -> Option<i32> {
+    system_time_to_secs(time).try_into().ok()
+}
+
+/// Windows FILETIME, the amount of 100 nanosecond intervals since 1601-01-01. Times before that
+/// are clamped to it.
+fn ntfs_timestamp(time: SystemTime) -> u64 {
+    let since_ntfs_epoch = match time.duration_since(UNIX_EPOCH) {
+        Ok(since_epoch) => since_epoch.saturating_add(NTFS_EPOCH_OFFSET),
+        Err(before_epoch) => NTFS_EPOCH_OFFSET.saturating_sub(before_epoch.duration()),
+    };
+    (since_ntfs_epoch.as_nanos() / 100)
+        .try_into()
+        .unwrap_or(u64::MAX)
 }
 
 const MOD_TIME_PRESENT: u8 = 1;
@@ -203,6 +354,86 @@ const AC_TIME_PRESENT: u8 = 1 << 1;
 const CR_TIME_PRESENT: u8 = 1 << 2;
 
 impl ExtraField {
+    /// Construct an [`UnixExtendedTimestamp`](Self::UnixExtendedTimestamp) field from
+    /// [`SystemTime`]s. Times that don't fit into the field, which are the ones before December
+    /// 1901 and after January 2038, are left out.
+    ///
+    /// ```
+    /// # use mtzip::ExtraField;
+    /// # use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    /// let mtime = UNIX_EPOCH + Duration::from_secs(1700000000);
+    /// let field = ExtraField::unix_extended_from_systemtime(Some(mtime), None, None);
+    /// assert_eq!(
+    ///     field,
+    ///     ExtraField::UnixExtendedTimestamp {
+    ///         mod_time: Some(1700000000),
+    ///         ac_time: None,
+    ///         cr_time: None,
+    ///     }
+    /// );
+    /// ```
+    pub fn unix_extended_from_systemtime(
+        mod_time: Option<SystemTime>,
+        ac_time: Option<SystemTime>,
+        cr_time: Option<SystemTime>,
+    ) -> Self {
+        Self::UnixExtendedTimestamp {
+            mod_time: mod_time.and_then(unix_timestamp),
+            ac_time: ac_time.and_then(unix_timestamp),
+            cr_time: cr_time.and_then(unix_timestamp),
+        }
+    }
+
+    /// Construct an [`Ntfs`](Self::Ntfs) field from [`SystemTime`]s, converted to 100 nanosecond
+    /// intervals since 1601-01-01. Times before that are clamped to it.
+    ///
+    /// ```
+    /// # use mtzip::ExtraField;
+    /// # use std::time::UNIX_EPOCH;
+    /// let field = ExtraField::ntfs_from_systemtime(UNIX_EPOCH, UNIX_EPOCH, UNIX_EPOCH);
+    /// let epoch = 116444736000000000;
+    /// assert_eq!(
+    ///     field,
+    ///     ExtraField::Ntfs {
+    ///         mtime: epoch,
+    ///         atime: epoch,
+    ///         ctime: epoch,
+    ///     }
+    /// );
+    /// ```
+    pub fn ntfs_from_systemtime(mtime: SystemTime, atime: SystemTime, ctime: SystemTime) -> Self {
+        Self::Ntfs {
+            mtime: ntfs_timestamp(mtime),
+            atime: ntfs_timestamp(atime),
+            ctime: ntfs_timestamp(ctime),
+        }
+    }
+
+    /// Construct a [`UnicodePath`](Self::UnicodePath) field for a file name, which must be the
+    /// same as the file name of the entry in the archive.
+    #[inline]
+    pub fn unicode_path(name: &str) -> Self {
+        Self::unicode_path_with_header_name(name, name.as_bytes())
+    }
+
+    /// Construct a [`UnicodePath`](Self::UnicodePath) field for an entry with a
+    /// [raw file name](crate::ZipFileHeader::raw_filename), which is `header_name`. Extractors
+    /// use `name` instead of it only if the header name wasn't changed since the field was added.
+    pub fn unicode_path_with_header_name(name: &str, header_name: &[u8]) -> Self {
+        let mut crc = Crc::new();
+        crc.update(header_name);
+        Self::UnicodePath {
+            name_crc: crc.sum(),
+            utf8_name: name.to_owned(),
+        }
+    }
+
+    /// Whether this is one of the fields that store timestamps
+    #[inline]
+    pub(crate) fn is_timestamp(&self) -> bool {
+        matches!(self, Self::UnixExtendedTimestamp { .. } | Self::Ntfs { .. })
+    }
+
     #[inline]
     fn header_id(&self) -> u16 {
         match self {
@@ -217,19 +448,16 @@ impl ExtraField {
                 cr_time: _,
             } => 0x5455,
             Self::UnixAttrs { uid: _, gid: _ } => 0x7875,
+            Self::UnicodePath {
+                name_crc: _,
+                utf8_name: _,
+            } => 0x7075,
+            Self::Raw { header_id, data: _ } => *header_id,
         }
     }
 
     #[inline]
-    const fn optional_field_size<T: Sized>(field: &Option<T>) -> u16 {
-        match field {
-            Some(_) => std::mem::size_of::<T>() as u16,
-            None => 0,
-        }
-    }
-
-    #[inline]
-    const fn field_size<const CENTRAL_HEADER: bool>(&self) -> u16 {
+    fn field_size<const CENTRAL_HEADER: bool>(&self) -> usize {
         match self {
             Self::Ntfs {
                 mtime: _,
@@ -241,18 +469,35 @@ impl ExtraField {
                 ac_time,
                 cr_time,
             } => {
-                1 + Self::optional_field_size(mod_time) + {
-                    if !CENTRAL_HEADER {
-                        Self::optional_field_size(ac_time) + Self::optional_field_size(cr_time)
-                    } else {
-                        0
-                    }
-                }
+                1 + 4 * Self::written_times::<CENTRAL_HEADER>(*mod_time, *ac_time, *cr_time)
+                    .flatten()
+                    .count()
             }
             Self::UnixAttrs { uid: _, gid: _ } => 11,
+            Self::UnicodePath {
+                name_crc: _,
+                utf8_name,
+            } => 5 + utf8_name.len(),
+            Self::Raw { header_id: _, data } => data.len(),
         }
     }
 
+    /// Timestamps of [`UnixExtendedTimestamp`](Self::UnixExtendedTimestamp) that are written in
+    /// the header, in order. The central header only contains the modification time.
+    #[inline]
+    fn written_times<const CENTRAL_HEADER: bool>(
+        mod_time: Option<i32>,
+        ac_time: Option<i32>,
+        cr_time: Option<i32>,
+    ) -> impl Iterator<Item = Option<i32>> {
+        let other_times = if CENTRAL_HEADER {
+            [None, None]
+        } else {
+            [ac_time, cr_time]
+        };
+        std::iter::once(mod_time).chain(other_times)
+    }
+
     #[inline]
     const fn if_present(val: Option<i32>, if_present: u8) -> u8 {
         match val {
@@ -261,17 +506,79 @@ impl ExtraField {
         }
     }
 
+    fn parse(header_id: u16, data: &[u8]) -> Option<Self> {
+        let u32_at = |i: usize| Some(u32::from_le_bytes(data.get(i..i + 4)?.try_into().ok()?));
+        let u64_at = |i: usize| Some(u64::from_le_bytes(data.get(i..i + 8)?.try_into().ok()?));
+        match header_id {
+            0x000a => {
+                // Only the layout written by this library is supported: a single tag with all
+                // three timestamps
+                let tag = u16::from_le_bytes(data.get(4..6)?.try_into().ok()?);
+                let tag_size = u16::from_le_bytes(data.get(6..8)?.try_into().ok()?);
+                if tag != 1 || tag_size != 24 {
+                    return None;
+                }
+                Some(Self::Ntfs {
+                    mtime: u64_at(8)?,
+                    atime: u64_at(16)?,
+                    ctime: u64_at(24)?,
+                })
+            }
+            0x5455 => {
+                let flags = *data.first()?;
+                let mut offset = 1;
+                let mut next_time = |present: u8| {
+                    if flags & present == 0 {
+                        return None;
+                    }
+                    let time = u32_at(offset)? as i32;
+                    offset += 4;
+                    Some(time)
+                };
+                let mod_time = next_time(MOD_TIME_PRESENT);
+                let ac_time = next_time(AC_TIME_PRESENT);
+                let cr_time = next_time(CR_TIME_PRESENT);
+                Some(Self::UnixExtendedTimestamp {
+                    mod_time,
+                    ac_time,
+                    cr_time,
+                })
+            }
+            0x7875 => {
+                // Only version 1 with 32-bit UID and GID is supported
+                if data.get(..2)? != [1, 4] || *data.get(6)? != 4 {
+                    return None;
+                }
+                Some(Self::UnixAttrs {
+                    uid: u32_at(2)?,
+                    gid: u32_at(7)?,
+                })
+            }
+            0x7075 => {
+                // Only version 1 is defined
+                if *data.first()? != 1 {
+                    return None;
+                }
+                Some(Self::UnicodePath {
+                    name_crc: u32_at(1)?,
+                    utf8_name: String::from_utf8(data[5..].to_vec()).ok()?,
+                })
+            }
+            _ => None,
+        }
+    }
+
     const NTFS_FIELD_LEN: usize = 32;
     const UNIX_ATTRS_LEN: usize = 11;
 
     pub(crate) fn write<W: Write, const CENTRAL_HEADER: bool>(
-        self,
+        &self,
         writer: &mut W,
     ) -> std::io::Result<()> {
         // Header ID
         writer.write_all(&self.header_id().to_le_bytes())?;
-        // Field data size
-        writer.write_all(&self.field_size::<CENTRAL_HEADER>().to_le_bytes())?;
+        // Field data size, which fits because the length of all fields is checked before writing
+        writer.write_all(&(self.field_size::<CENTRAL_HEADER>() as u16).to_le_bytes())?;
 
         match self {
             Self::Ntfs {
@@ -307,20 +614,17 @@ impl ExtraField {
                 ac_time,
                 cr_time,
             } => {
-                let flags = Self::if_present(mod_time, MOD_TIME_PRESENT)
-                    | Self::if_present(ac_time, AC_TIME_PRESENT)
-                    | Self::if_present(cr_time, CR_TIME_PRESENT);
+                // Flags. As Info-ZIP does, the central header has the same flags as the local
+                // header, describing the times present in the local header, even though only the
+                // modification time is written there.
+                let flags = Self::if_present(*mod_time, MOD_TIME_PRESENT)
+                    | Self::if_present(*ac_time, AC_TIME_PRESENT)
+                    | Self::if_present(*cr_time, CR_TIME_PRESENT);
                 writer.write_all(&[flags])?;
-                if let Some(mod_time) = mod_time {
-                    writer.write_all(&mod_time.to_le_bytes())?;
-                }
-                if !CENTRAL_HEADER {
-                    if let Some(ac_time) = ac_time {
-                        writer.write_all(&ac_time.to_le_bytes())?;
-                    }
-                    if let Some(cr_time) = cr_time {
-                        writer.write_all(&cr_time.to_le_bytes())?;
-                    }
+                for time in
+                    Self::written_times::<CENTRAL_HEADER>(*mod_time, *ac_time, *cr_time).flatten()
+                {
+                    writer.write_all(&time.to_le_bytes())?;
                 }
             }
             Self::UnixAttrs { uid, gid } => {
@@ -343,6 +647,20 @@ impl ExtraField {
 
                 writer.write_all(&field)?;
             }
+            Self::UnicodePath {
+                name_crc,
+                utf8_name,
+            } => {
+                // Version of the field
+                writer.write_all(&[1])?;
+                // CRC32 of the header file name
+                writer.write_all(&name_crc.to_le_bytes())?;
+                // UTF-8 file name
+                writer.write_all(utf8_name.as_bytes())?;
+            }
+            Self::Raw { header_id: _, data } => {
+                writer.write_all(data)?;
+            }
         }
 
         Ok(())
@@ -1,62 +1,299 @@
-use std::io::{Seek, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
 
 use cfg_if::cfg_if;
+use flate2::{read::DeflateDecoder, CrcReader};
 
-use super::extra_field::ExtraFields;
-use crate::CompressionType;
+use super::extra_field::{ExtraField, ExtraFields, ZIP64_EXTRA_FIELD_ID};
+use crate::{level::CompressionLevel, CompressionType, Error, HostOs};
 
-const LOCAL_FILE_HEADER_SIGNATURE: u32 = 0x04034B50;
-const CENTRAL_FILE_HEADER_SIGNATURE: u32 = 0x02014B50;
+pub(crate) const LOCAL_FILE_HEADER_SIGNATURE: u32 = 0x04034B50;
+pub(crate) const CENTRAL_FILE_HEADER_SIGNATURE: u32 = 0x02014B50;
+pub(crate) const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074B50;
 
+/// Version 1.0 of the specification is enough for stored files
+const STORED_VERSION_NEEDED_TO_EXTRACT: u16 = 10;
+/// Version 2.0 of the specification introduced deflate, directories and traditional encryption
 const VERSION_NEEDED_TO_EXTRACT: u16 = 20;
-#[cfg(not(target_os = "windows"))]
-/// OS - Unix assumed, id 3
+/// Version 2.1 of the specification introduced Deflate64
+const DEFLATE64_VERSION_NEEDED_TO_EXTRACT: u16 = 21;
+/// Version 4.5 of the specification is required for ZIP64 extensions
+pub(crate) const ZIP64_VERSION_NEEDED_TO_EXTRACT: u16 = 45;
 /// Specification version 6.2
-const VERSION_MADE_BY: u16 = (3 << 8) + 62;
-#[cfg(target_os = "windows")]
-/// OS - Windows, id 11 per Info-Zip spec
-/// Specification version 6.2
-const VERSION_MADE_BY: u16 = (11 << 8) + 62;
+const SPECIFICATION_VERSION: u16 = 62;
+
+/// Value of the version made by field: the host OS in the high byte and the specification version
+/// in the low byte
+#[inline]
+pub(crate) const fn version_made_by(host_os: HostOs) -> u16 {
+    ((host_os as u16) << 8) + SPECIFICATION_VERSION
+}
 
 #[allow(dead_code)]
 pub(crate) const DEFAULT_UNIX_FILE_ATTRS: u16 = 0o100644;
 #[allow(dead_code)]
 pub(crate) const DEFAULT_UNIX_DIR_ATTRS: u16 = 0o040755;
+/// `S_IFLNK` file type with all permissions, as created by `ln -s`
+pub(crate) const DEFAULT_UNIX_SYMLINK_ATTRS: u16 = 0o120777;
 
 #[cfg(target_os = "windows")]
 pub(crate) const DEFAULT_WINDOWS_FILE_ATTRS: u16 = 128;
 #[cfg(target_os = "windows")]
 pub(crate) const DEFAULT_WINDOWS_DIR_ATTRS: u16 = 16;
 
+/// Android zipalign extra field
+const ALIGNMENT_EXTRA_FIELD_ID: u16 = 0xD935;
+
 /// Set bit 11 to indicate that the file names are in UTF-8, because all strings in rust are valid
 /// UTF-8
-const GENERAL_PURPOSE_BIT_FLAG: u16 = 1 << 11;
+pub(crate) const GENERAL_PURPOSE_BIT_FLAG: u16 = 1 << 11;
+/// Bit 0 is set for encrypted files
+const ENCRYPTED_BIT_FLAG: u16 = 1;
+/// Bit 3 is set when the checksum and sizes are in a data descriptor after the data
+const DATA_DESCRIPTOR_BIT_FLAG: u16 = 1 << 3;
+/// Bits that depend on how the entry is written: encryption, data descriptor, strong encryption,
+/// UTF-8 names and encrypted central directory
+const MANAGED_BIT_FLAGS: u16 =
+    ENCRYPTED_BIT_FLAG | DATA_DESCRIPTOR_BIT_FLAG | 1 << 6 | GENERAL_PURPOSE_BIT_FLAG | 1 << 13;
+
+/// Sizes and offsets from this value on are stored in the ZIP64 extra field, and the 32-bit field
+/// in the header is set to this value
+const ZIP64_MARKER: u64 = u32::MAX as u64;
+
+/// Value needs to be stored in the ZIP64 extra field
+#[inline]
+const fn needs_zip64(value: u64) -> bool {
+    value >= ZIP64_MARKER
+}
+
+/// Value as stored in a 32-bit field of the headers, the marker if it's in the ZIP64 extra field
+#[inline]
+const fn header_u32(value: u64) -> u32 {
+    if needs_zip64(value) {
+        u32::MAX
+    } else {
+        value as u32
+    }
+}
+
+/// Convert a UNIX timestamp (seconds since epoch, UTC) to MS-DOS time and date, in that order.
+///
+/// MS-DOS timestamps have a 2 second resolution and can only represent years from 1980 to 2107,
+/// timestamps outside of this range are clamped.
+pub fn dos_datetime(secs_since_epoch: i64) -> (u16, u16) {
+    const DOS_EPOCH: i64 = 315532800; // 1980-01-01T00:00:00Z
+    const DOS_MAX: i64 = 4354819199; // 2107-12-31T23:59:59Z
+
+    let secs = secs_since_epoch.clamp(DOS_EPOCH, DOS_MAX);
+    let days = secs.div_euclid(86400);
+    let secs_of_day = secs.rem_euclid(86400);
+
+    // Civil date from days since epoch, by Howard Hinnant
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    let hour = secs_of_day / 3600;
+    let minute = secs_of_day % 3600 / 60;
+    let second = secs_of_day % 60;
+
+    let time = ((hour << 11) | (minute << 5) | (second / 2)) as u16;
+    let date = (((year - 1980) << 9) | (month << 5) | day) as u16;
+    (time, date)
+}
+
+/// Convert MS-DOS time and date to a UNIX timestamp (seconds since epoch, UTC). Inverse of
+/// [`dos_datetime`].
+pub fn unix_from_dos_datetime(time: u16, date: u16) -> i64 {
+    let year = i64::from(date >> 9) + 1980;
+    let month = i64::from((date >> 5) & 0xF).clamp(1, 12);
+    let day = i64::from(date & 0x1F).max(1);
+
+    // Days since epoch from civil date, by Howard Hinnant
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
 
+    let hour = i64::from(time >> 11);
+    let minute = i64::from((time >> 5) & 0x3F);
+    let second = i64::from(time & 0x1F) * 2;
+
+    days * 86400 + hour * 3600 + minute * 60 + second
+}
+
+/// Compressed file that is ready to be written, with all of its header fields. Usually produced
+/// by compressing the files added to a [`ZipArchive`](crate::ZipArchive), but it can be built by
+/// hand too and added with [`ZipArchive::push_prebuilt`](crate::ZipArchive::push_prebuilt).
+///
+/// The data is written as-is, so it has to match the header: it must be compressed with
+/// [`compression_type`](ZipFileHeader::compression_type), and if the header is
+/// [`encrypted`](ZipFileHeader::encrypted), it must include the 12 byte encryption header.
+///
+/// ```
+/// # use mtzip::{CompressionType, ZipArchive, ZipFile, ZipFileHeader};
+/// let data = b"Hello, world!".to_vec();
+/// let mut crc = flate2::Crc::new();
+/// crc.update(&data);
+///
+/// let mut header = ZipFileHeader::new(
+///     "hello_world.txt",
+///     CompressionType::Stored,
+///     crc.sum(),
+///     data.len() as u64,
+/// );
+/// header.file_comment = Some("Built by hand".to_owned());
+///
+/// let mut zipper = ZipArchive::new();
+/// zipper.push_prebuilt(ZipFile::new(header, data));
+/// ```
 #[derive(Debug)]
 pub struct ZipFile {
+    /// Header fields of the file
     pub header: ZipFileHeader,
+    /// Compressed data of the file
     pub data: Vec<u8>,
 }
 
+/// Header fields of a [`ZipFile`], written to both its local header and its central directory
+/// entry. Fields that depend on how the file is written, such as offsets and the compressed size,
+/// are filled in when writing.
 #[derive(Debug)]
 pub struct ZipFileHeader {
+    /// Compression type the data is compressed with
     pub compression_type: CompressionType,
+    /// CRC-32 of the uncompressed data
     pub crc: u32,
-    pub uncompressed_size: u32,
+    /// Size of the uncompressed data. Can exceed the 4 GiB limit of the headers, which is checked
+    /// when writing them.
+    pub uncompressed_size: u64,
+    /// Path of the file in the archive
     pub filename: String,
+    /// Bytes written as the path instead of [`filename`](Self::filename), in an encoding other
+    /// than UTF-8, such as a legacy code page. The UTF-8 flag is not set for the file then.
+    /// `filename` is still used for everything else, such as sorting and checking for duplicates.
+    pub raw_filename: Option<Vec<u8>>,
+    /// File comment, must not be longer than 65535 bytes
     pub file_comment: Option<String>,
+    /// Last modification time in seconds since UNIX epoch
+    pub modification_time: Option<i64>,
+    /// External file attributes, with the UNIX mode in the high 16 bits and MS-DOS attributes in
+    /// the low 16 bits
     pub external_file_attributes: u32,
+    /// Operating system written to the central directory for this file instead of the one set
+    /// for the whole archive with [`set_host_os`](crate::ZipArchive::set_host_os)
+    pub host_os: Option<HostOs>,
+    /// Bit 0 is set for text files
+    pub internal_file_attributes: u16,
+    /// Extra fields written to the headers
     pub extra_fields: ExtraFields,
+    /// Alignment of the file data in the archive, achieved by padding the local header
+    pub alignment: Option<u16>,
+    /// Data is encrypted with traditional PKWARE encryption
+    pub encrypted: bool,
+    /// Additional bits of the general purpose bit flag. Bits that are managed by this library are
+    /// ignored.
+    pub extra_flags: u16,
+    /// Checksum and sizes follow the data in a data descriptor instead of the local header
+    pub(crate) data_descriptor: bool,
+    /// Data was stored as-is because compressing it didn't make it smaller
+    pub(crate) stored_fallback: bool,
+    /// Level the data was compressed with, reused when it has to be compressed again
+    pub(crate) compression_level: CompressionLevel,
+}
+
+impl ZipFileHeader {
+    /// Create a header for a file with data compressed with `compression_type`, given the
+    /// checksum and size of the uncompressed data. The other fields are empty, and the external
+    /// attributes are the defaults used for files.
+    pub fn new(
+        filename: impl Into<String>,
+        compression_type: CompressionType,
+        crc: u32,
+        uncompressed_size: u64,
+    ) -> Self {
+        Self {
+            compression_type,
+            crc,
+            uncompressed_size,
+            filename: filename.into(),
+            raw_filename: None,
+            host_os: None,
+            file_comment: None,
+            modification_time: None,
+            external_file_attributes: ZipFile::external_file_attributes(
+                ZipFile::default_file_attrs(),
+                0,
+            ),
+            internal_file_attributes: 0,
+            extra_fields: ExtraFields::default(),
+            alignment: None,
+            encrypted: false,
+            extra_flags: 0,
+            data_descriptor: false,
+            stored_fallback: false,
+            compression_level: CompressionLevel::default(),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct ZipFileNoData {
     pub header: ZipFileHeader,
-    pub local_header_offset: u32,
-    pub compressed_size: u32,
+    pub local_header_offset: u64,
+    pub compressed_size: u64,
+    /// Volume of a split archive that the local header is in
+    pub disk_number_start: u16,
+    /// Local header requires ZIP64 support, so the central directory entry does too
+    pub zip64: bool,
 }
 
 impl ZipFile {
+    /// Create a file from its header and compressed data
+    #[inline]
+    pub fn new(header: ZipFileHeader, data: Vec<u8>) -> Self {
+        Self { header, data }
+    }
+
+    /// Compare the CRC-32 in the header to the one of the decompressed data. Data compressed with
+    /// a method this library can't decompress is assumed to be correct.
+    pub(crate) fn verify_crc(&self) -> Result<(), Error> {
+        fn crc_of<R: Read>(reader: R) -> std::io::Result<u32> {
+            let mut reader = CrcReader::new(reader);
+            std::io::copy(&mut reader, &mut std::io::sink())?;
+            Ok(reader.crc().sum())
+        }
+        let crc = match self.header.compression_type {
+            CompressionType::Stored => crc_of(self.data.as_slice()),
+            CompressionType::Deflate => crc_of(DeflateDecoder::new(self.data.as_slice())),
+            CompressionType::Deflate64 | CompressionType::Default => return Ok(()),
+        };
+        // Data that can't be decompressed can't match the checksum either
+        if crc.ok() == Some(self.header.crc) {
+            Ok(())
+        } else {
+            Err(Error::CrcMismatch(self.header.filename.clone()))
+        }
+    }
+
+    /// Combine UNIX mode, stored in the high 16 bits, and MS-DOS attributes, stored in the low 16
+    /// bits, into the value of the external file attributes
+    #[inline]
+    pub(crate) const fn external_file_attributes(
+        external_attributes: u16,
+        dos_attributes: u16,
+    ) -> u32 {
+        (external_attributes as u32) << 16 | dos_attributes as u32
+    }
+
     pub(crate) const fn default_file_attrs() -> u16 {
         cfg_if! {
             if #[cfg(target_os = "windows")] {
@@ -81,23 +318,290 @@ impl ZipFile {
         }
     }
 
-    pub fn write_local_file_header_with_data_consuming<W: Write + Seek>(
+    pub(crate) fn write_local_file_header_with_data_consuming<W: Write + Seek>(
         self,
         buf: &mut W,
     ) -> std::io::Result<ZipFileNoData> {
-        let local_header_offset = super::stream_position_u32(buf)?;
-        self.write_local_file_header_and_data(buf)?;
+        let local_header_offset = buf.stream_position()?;
+        let zip64 = self.write_local_file_header_and_data(buf, local_header_offset, false)?;
         let Self { header, data } = self;
         Ok(ZipFileNoData {
             header,
             local_header_offset,
-            compressed_size: data.len() as u32,
+            compressed_size: data.len() as u64,
+            disk_number_start: 0,
+            zip64,
+        })
+    }
+
+    /// Write the local header of a file whose data isn't known yet, then the data with
+    /// `write_data`, which returns the crc and uncompressed size of the data. The checksum and
+    /// sizes in the header are filled in afterwards by seeking back.
+    ///
+    /// Sizes of 4 GiB or more can only be filled in if space for them was reserved with
+    /// `reserve_zip64`, otherwise [`Error::EntryTooLarge`] is returned.
+    pub(crate) fn write_local_file_header_with_streamed_data<W: Write + Seek>(
+        self,
+        buf: &mut W,
+        reserve_zip64: bool,
+        write_data: impl FnOnce(&mut W) -> std::io::Result<(u32, u64)>,
+    ) -> std::io::Result<ZipFileNoData> {
+        debug_assert!(self.data.is_empty());
+        let local_header_offset = buf.stream_position()?;
+        let zip64 =
+            self.write_local_file_header_and_data(buf, local_header_offset, reserve_zip64)?;
+        let Self { mut header, .. } = self;
+
+        let data_offset = buf.stream_position()?;
+        let (crc, uncompressed_size) = write_data(buf)?;
+        let data_end = buf.stream_position()?;
+        let compressed_size = data_end - data_offset;
+        if !reserve_zip64 && (needs_zip64(compressed_size) || needs_zip64(uncompressed_size)) {
+            return Err(Error::EntryTooLarge.into());
+        }
+
+        // crc
+        buf.seek(SeekFrom::Start(
+            local_header_offset + Self::LOCAL_FILE_HEADER_CRC_OFFSET,
+        ))?;
+        buf.write_all(&crc.to_le_bytes())?;
+        if reserve_zip64 {
+            // Sizes in the ZIP64 extra field, which comes right after the file name
+            buf.seek(SeekFrom::Start(
+                local_header_offset
+                    + (Self::LOCAL_FILE_HEADER_LEN + header.filename_bytes().len()) as u64
+                    + 4,
+            ))?;
+            buf.write_all(&uncompressed_size.to_le_bytes())?;
+            buf.write_all(&compressed_size.to_le_bytes())?;
+        } else {
+            // Compressed size
+            buf.write_all(&(compressed_size as u32).to_le_bytes())?;
+            // Uncompressed size
+            buf.write_all(&(uncompressed_size as u32).to_le_bytes())?;
+        }
+        buf.seek(SeekFrom::Start(data_end))?;
+
+        header.crc = crc;
+        header.uncompressed_size = uncompressed_size;
+        Ok(ZipFileNoData {
+            header,
+            local_header_offset,
+            compressed_size,
+            disk_number_start: 0,
+            zip64,
+        })
+    }
+
+    /// Write the local header of a file whose data isn't known yet, then the data with
+    /// `write_data`, which returns the crc and uncompressed size of the data. The checksum and
+    /// sizes are written in a data descriptor after the data, so the header is written only once
+    /// and no seeking is needed, other than querying the position.
+    ///
+    /// Sizes of 4 GiB or more can only be written if the descriptor was announced to have ZIP64
+    /// sizes with `reserve_zip64`, otherwise [`Error::EntryTooLarge`] is returned.
+    pub(crate) fn write_local_file_header_with_data_descriptor<W: Write + Seek>(
+        mut self,
+        buf: &mut W,
+        reserve_zip64: bool,
+        write_data: impl FnOnce(&mut W) -> std::io::Result<(u32, u64)>,
+    ) -> std::io::Result<ZipFileNoData> {
+        debug_assert!(self.data.is_empty());
+        self.header.data_descriptor = true;
+        let local_header_offset = buf.stream_position()?;
+        let zip64 =
+            self.write_local_file_header_and_data(buf, local_header_offset, reserve_zip64)?;
+        let Self { mut header, .. } = self;
+
+        let data_offset = buf.stream_position()?;
+        let (crc, uncompressed_size) = write_data(buf)?;
+        let compressed_size = buf.stream_position()? - data_offset;
+        if !reserve_zip64 && (needs_zip64(compressed_size) || needs_zip64(uncompressed_size)) {
+            return Err(Error::EntryTooLarge.into());
+        }
+
+        let mut descriptor = [0; Self::ZIP64_DATA_DESCRIPTOR_LEN];
+        let descriptor_len = {
+            let mut descriptor_buf: &mut [u8] = &mut descriptor;
+
+            // signature
+            descriptor_buf.write_all(&DATA_DESCRIPTOR_SIGNATURE.to_le_bytes())?;
+            // crc
+            descriptor_buf.write_all(&crc.to_le_bytes())?;
+            // Readers expect 8 byte sizes if the local header has a ZIP64 extra field
+            if reserve_zip64 {
+                // Compressed size
+                descriptor_buf.write_all(&compressed_size.to_le_bytes())?;
+                // Uncompressed size
+                descriptor_buf.write_all(&uncompressed_size.to_le_bytes())?;
+                Self::ZIP64_DATA_DESCRIPTOR_LEN
+            } else {
+                // Compressed size
+                descriptor_buf.write_all(&(compressed_size as u32).to_le_bytes())?;
+                // Uncompressed size
+                descriptor_buf.write_all(&(uncompressed_size as u32).to_le_bytes())?;
+                Self::DATA_DESCRIPTOR_LEN
+            }
+        };
+
+        buf.write_all(&descriptor[..descriptor_len])?;
+
+        header.crc = crc;
+        header.uncompressed_size = uncompressed_size;
+        Ok(ZipFileNoData {
+            header,
+            local_header_offset,
+            compressed_size,
+            disk_number_start: 0,
+            zip64,
         })
     }
 
-    const LOCAL_FILE_HEADER_LEN: usize = 30;
+    /// Largest possible size of the local header, before the file is positioned in the archive
+    pub(crate) fn local_header_max_len(&self) -> u64 {
+        let alignment_field_len = match self.header.alignment {
+            Some(alignment) if alignment > 1 => Self::ALIGNMENT_FIELD_MIN_LEN + alignment as usize,
+            _ => 0,
+        };
+        (Self::LOCAL_FILE_HEADER_LEN
+            + self.header.filename_bytes().len()
+            + self.header.extra_fields.data_length::<false>()
+            + self.local_zip64_field_len(false)
+            + alignment_field_len) as u64
+    }
+
+    pub(crate) const LOCAL_FILE_HEADER_LEN: usize = 30;
+    /// Offset of the crc in the local header, followed by the compressed and uncompressed sizes
+    const LOCAL_FILE_HEADER_CRC_OFFSET: u64 = 14;
+    /// Signature, crc and both sizes
+    pub(crate) const DATA_DESCRIPTOR_LEN: usize = 16;
+    /// Signature, crc and both sizes as 8 byte values
+    pub(crate) const ZIP64_DATA_DESCRIPTOR_LEN: usize = 24;
+    /// Header ID, size and the alignment value
+    const ALIGNMENT_FIELD_MIN_LEN: usize = 6;
+    /// Header ID, size and both sizes, which the local header always has together
+    const LOCAL_ZIP64_FIELD_LEN: usize = 20;
+
+    /// Length of the ZIP64 extra field in the local header. It's needed if one of the sizes
+    /// doesn't fit into the header, or if it's reserved for sizes that aren't known yet.
+    #[inline]
+    fn local_zip64_field_len(&self, reserve_zip64: bool) -> usize {
+        if reserve_zip64
+            || needs_zip64(self.data.len() as u64)
+            || needs_zip64(self.header.uncompressed_size)
+        {
+            Self::LOCAL_ZIP64_FIELD_LEN
+        } else {
+            0
+        }
+    }
+
+    /// Amount of padding needed for a local header with a file name and extra fields of these
+    /// lengths, so that the data is aligned to `alignment`
+    fn padding_for(
+        alignment: Option<u16>,
+        local_header_offset: u64,
+        filename_len: usize,
+        extra_fields_len: usize,
+    ) -> Option<u16> {
+        let alignment = alignment.filter(|&alignment| alignment > 1)? as u64;
+        let data_offset = local_header_offset
+            + (Self::LOCAL_FILE_HEADER_LEN
+                + filename_len
+                + extra_fields_len
+                + Self::ALIGNMENT_FIELD_MIN_LEN) as u64;
+        Some(((alignment - data_offset % alignment) % alignment) as u16)
+    }
+
+    /// Length of the local header together with the data, and of the central directory entry,
+    /// when the file is written at `local_header_offset`. Takes the alignment and timestamp
+    /// stripping that are applied when writing into account.
+    pub(crate) fn written_len(
+        &self,
+        local_header_offset: u64,
+        stored_alignment: Option<u16>,
+        strip_timestamps: bool,
+    ) -> (u64, u64) {
+        let header = &self.header;
+        let include = |field: &ExtraField| !(strip_timestamps && field.is_timestamp());
+        let local_extra_fields_len = header.extra_fields.data_length_of::<false>(include);
+        let central_extra_fields_len = header.extra_fields.data_length_of::<true>(include);
+        let alignment = match header.alignment {
+            None if header.compression_type == CompressionType::Stored => stored_alignment,
+            alignment => alignment,
+        };
+        let filename_len = header.filename_bytes().len();
+        let local_zip64_field_len = self.local_zip64_field_len(false);
+        let alignment_field_len = Self::padding_for(
+            alignment,
+            local_header_offset,
+            filename_len,
+            local_extra_fields_len + local_zip64_field_len,
+        )
+        .map_or(0, |padding| {
+            Self::ALIGNMENT_FIELD_MIN_LEN + padding as usize
+        });
+        let file_comment_len = header.file_comment.as_ref().map_or(0, String::len);
+        let central_zip64_field_len = ZipFileNoData::central_zip64_values(
+            header.uncompressed_size,
+            self.data.len() as u64,
+            local_header_offset,
+        )
+        .len;
+        let local_len = Self::LOCAL_FILE_HEADER_LEN
+            + filename_len
+            + local_extra_fields_len
+            + local_zip64_field_len
+            + alignment_field_len
+            + self.data.len();
+        let central_len = ZipFileNoData::CENTRAL_DIR_ENTRY_LEN
+            + filename_len
+            + central_extra_fields_len
+            + central_zip64_field_len
+            + file_comment_len;
+        (local_len as u64, central_len as u64)
+    }
+
+    /// Use the default alignment for stored files that don't have an alignment set
+    #[inline]
+    pub(crate) fn apply_default_alignment(&mut self, stored_alignment: Option<u16>) {
+        if self.header.alignment.is_none()
+            && self.header.compression_type == CompressionType::Stored
+        {
+            self.header.alignment = stored_alignment;
+        }
+    }
+
+    /// Write the local header followed by the data, aligning the data if needed. Returns whether
+    /// the header requires ZIP64 support, either for the sizes or because the header itself is
+    /// beyond 4 GiB.
+    pub(crate) fn write_local_file_header_and_data<W: Write>(
+        &self,
+        buf: &mut W,
+        local_header_offset: u64,
+        reserve_zip64: bool,
+    ) -> std::io::Result<bool> {
+        let zip64_field_len = self.local_zip64_field_len(reserve_zip64);
+        let extra_fields_len = self.header.extra_fields.data_length::<false>() + zip64_field_len;
+        let alignment_padding = Self::padding_for(
+            self.header.alignment,
+            local_header_offset,
+            self.header.filename_bytes().len(),
+            extra_fields_len,
+        );
+        let alignment_field_len = alignment_padding
+            .map(|padding| Self::ALIGNMENT_FIELD_MIN_LEN + padding as usize)
+            .unwrap_or(0);
+        let extra_field_len = u16::try_from(extra_fields_len + alignment_field_len)
+            .map_err(|_| Error::ExtraFieldTooLong(self.header.filename.clone()))?;
+        let zip64 = zip64_field_len > 0 || needs_zip64(local_header_offset);
+        // Sizes that are not known yet are zero until they are filled in
+        let (compressed_size, uncompressed_size) = if reserve_zip64 {
+            (0, 0)
+        } else {
+            (self.data.len() as u64, self.header.uncompressed_size)
+        };
 
-    pub fn write_local_file_header_and_data<W: Write>(&self, buf: &mut W) -> std::io::Result<()> {
         // Writing to a temporary in-memory statically sized array first
         let mut header = [0; Self::LOCAL_FILE_HEADER_LEN];
         {
@@ -106,77 +610,208 @@ impl ZipFile {
             // signature
             header_buf.write_all(&LOCAL_FILE_HEADER_SIGNATURE.to_le_bytes())?;
             // version needed to extract
-            header_buf.write_all(&VERSION_NEEDED_TO_EXTRACT.to_le_bytes())?;
+            header_buf.write_all(&self.header.version_needed(zip64).to_le_bytes())?;
             // general purpose bit flag
-            header_buf.write_all(&GENERAL_PURPOSE_BIT_FLAG.to_le_bytes())?;
+            header_buf.write_all(&self.header.general_purpose_flag().to_le_bytes())?;
             // compression type
             header_buf.write_all(&(self.header.compression_type as u16).to_le_bytes())?;
-            // Last modification time // moved to extra fields
-            header_buf.write_all(&0_u16.to_le_bytes())?;
-            // Last modification date // moved to extra fields
-            header_buf.write_all(&0_u16.to_le_bytes())?;
+            let (dos_time, dos_date) = self.header.dos_datetime();
+            // Last modification time
+            header_buf.write_all(&dos_time.to_le_bytes())?;
+            // Last modification date
+            header_buf.write_all(&dos_date.to_le_bytes())?;
             // crc
             header_buf.write_all(&self.header.crc.to_le_bytes())?;
+            // Both sizes are in the ZIP64 extra field if it's there
+            let (compressed_size, uncompressed_size) = if zip64_field_len > 0 {
+                (u32::MAX, u32::MAX)
+            } else {
+                (compressed_size as u32, uncompressed_size as u32)
+            };
             // Compressed size
-            debug_assert!(self.data.len() <= u32::MAX as usize);
-            header_buf.write_all(&(self.data.len() as u32).to_le_bytes())?;
+            header_buf.write_all(&compressed_size.to_le_bytes())?;
             // Uncompressed size
-            header_buf.write_all(&self.header.uncompressed_size.to_le_bytes())?;
+            header_buf.write_all(&uncompressed_size.to_le_bytes())?;
             // Filename size
-            debug_assert!(self.header.filename.len() <= u16::MAX as usize);
-            header_buf.write_all(&(self.header.filename.len() as u16).to_le_bytes())?;
+            let filename_len = u16::try_from(self.header.filename_bytes().len())
+                .map_err(|_| Error::FilenameTooLong(self.header.filename.clone()))?;
+            header_buf.write_all(&filename_len.to_le_bytes())?;
             // extra field size
-            header_buf.write_all(
-                &self
-                    .header
-                    .extra_fields
-                    .data_length::<false>()
-                    .to_le_bytes(),
-            )?;
+            header_buf.write_all(&extra_field_len.to_le_bytes())?;
         }
 
         buf.write_all(&header)?;
 
         // Filename
-        buf.write_all(self.header.filename.as_bytes())?;
+        buf.write_all(self.header.filename_bytes())?;
+        // ZIP64 extra field, first so that its position is known when patching the sizes
+        if zip64_field_len > 0 {
+            // Header ID
+            buf.write_all(&ZIP64_EXTRA_FIELD_ID.to_le_bytes())?;
+            // Field data size
+            buf.write_all(&((zip64_field_len - 4) as u16).to_le_bytes())?;
+            // Uncompressed size
+            buf.write_all(&uncompressed_size.to_le_bytes())?;
+            // Compressed size
+            buf.write_all(&compressed_size.to_le_bytes())?;
+        }
         // Extra field
         self.header.extra_fields.write::<_, false>(buf)?;
+        // Alignment extra field, as written by zipalign
+        if let Some(padding) = alignment_padding {
+            // Header ID
+            buf.write_all(&ALIGNMENT_EXTRA_FIELD_ID.to_le_bytes())?;
+            // Field data size
+            buf.write_all(&(2 + padding).to_le_bytes())?;
+            // Alignment
+            buf.write_all(&self.header.alignment.unwrap_or_default().to_le_bytes())?;
+            // Padding
+            buf.write_all(&vec![0; padding as usize])?;
+        }
 
         // Data
         buf.write_all(&self.data)?;
 
-        Ok(())
+        Ok(zip64)
     }
 
+    /// Remove everything that depends on when the file was created or modified
+    pub(crate) fn strip_timestamps(&mut self) {
+        self.header.strip_timestamps();
+    }
+}
+
+impl ZipFileHeader {
     #[inline]
-    pub fn directory(
-        mut name: String,
-        extra_fields: ExtraFields,
-        external_attributes: u16,
-        file_comment: Option<String>,
-    ) -> Self {
-        if !(name.ends_with('/') || name.ends_with('\\')) {
-            name += "/"
-        };
-        Self {
-            header: ZipFileHeader {
-                compression_type: CompressionType::Stored,
-                crc: 0,
-                uncompressed_size: 0,
-                filename: name,
-                external_file_attributes: (external_attributes as u32) << 16,
-                extra_fields,
-                file_comment,
-            },
-            data: vec![],
+    fn general_purpose_flag(&self) -> u16 {
+        let mut flag = self.extra_flags & !MANAGED_BIT_FLAGS;
+        if self.raw_filename.is_none() {
+            flag |= GENERAL_PURPOSE_BIT_FLAG;
+        }
+        if self.encrypted {
+            flag |= ENCRYPTED_BIT_FLAG;
+        }
+        if self.data_descriptor {
+            flag |= DATA_DESCRIPTOR_BIT_FLAG;
+        }
+        flag
+    }
+
+    /// Lowest version of the specification an extractor has to support to extract this file,
+    /// written in the "version needed to extract" field of both headers. Depends on the
+    /// compression type, whether the file is encrypted and whether it's a directory.
+    ///
+    /// ```
+    /// # use mtzip::{CompressionType, ZipFileHeader};
+    /// let stored = ZipFileHeader::new("stored.txt", CompressionType::Stored, 0, 0);
+    /// assert_eq!(stored.version_needed_to_extract(), 10);
+    ///
+    /// let deflated = ZipFileHeader::new("deflated.txt", CompressionType::Deflate, 0, 0);
+    /// assert_eq!(deflated.version_needed_to_extract(), 20);
+    ///
+    /// let dir = ZipFileHeader::new("dir/", CompressionType::Stored, 0, 0);
+    /// assert_eq!(dir.version_needed_to_extract(), 20);
+    /// ```
+    pub fn version_needed_to_extract(&self) -> u16 {
+        match self.compression_type {
+            CompressionType::Deflate64 => DEFLATE64_VERSION_NEEDED_TO_EXTRACT,
+            CompressionType::Deflate | CompressionType::Default => VERSION_NEEDED_TO_EXTRACT,
+            CompressionType::Stored if self.encrypted || self.filename.ends_with('/') => {
+                VERSION_NEEDED_TO_EXTRACT
+            }
+            CompressionType::Stored => STORED_VERSION_NEEDED_TO_EXTRACT,
         }
     }
+
+    /// Version needed to extract, raised to the one introducing ZIP64 if the headers use it
+    #[inline]
+    fn version_needed(&self, zip64: bool) -> u16 {
+        if zip64 {
+            self.version_needed_to_extract()
+                .max(ZIP64_VERSION_NEEDED_TO_EXTRACT)
+        } else {
+            self.version_needed_to_extract()
+        }
+    }
+
+    /// Path of the file as written to the headers
+    #[inline]
+    pub(crate) fn filename_bytes(&self) -> &[u8] {
+        self.raw_filename
+            .as_deref()
+            .unwrap_or(self.filename.as_bytes())
+    }
+
+    /// Remove everything that depends on when the file was created or modified
+    pub(crate) fn strip_timestamps(&mut self) {
+        self.modification_time = None;
+        self.extra_fields
+            .values
+            .retain(|field| !field.is_timestamp());
+    }
+
+    /// MS-DOS time and date of the last modification. If no modification time is known, the
+    /// earliest MS-DOS date is used.
+    #[inline]
+    fn dos_datetime(&self) -> (u16, u16) {
+        dos_datetime(self.modification_time.unwrap_or(i64::MIN))
+    }
+}
+
+/// Values of a central directory entry that are stored in its ZIP64 extra field, in the order
+/// they are written
+struct CentralZip64Values {
+    values: [u64; 3],
+    count: usize,
+    /// Length of the whole extra field, 0 if it's not needed
+    len: usize,
 }
 
 impl ZipFileNoData {
-    const CENTRAL_DIR_ENTRY_LEN: usize = 46;
+    pub(crate) const CENTRAL_DIR_ENTRY_LEN: usize = 46;
+
+    /// Values that don't fit into the central directory entry. Unlike in the local header, only
+    /// those are written to the ZIP64 extra field.
+    fn central_zip64_values(
+        uncompressed_size: u64,
+        compressed_size: u64,
+        local_header_offset: u64,
+    ) -> CentralZip64Values {
+        let mut values = [0; 3];
+        let mut count = 0;
+        for value in [uncompressed_size, compressed_size, local_header_offset] {
+            if needs_zip64(value) {
+                values[count] = value;
+                count += 1;
+            }
+        }
+        let len = if count > 0 { 4 + 8 * count } else { 0 };
+        CentralZip64Values { values, count, len }
+    }
+
+    pub fn write_central_directory_entry<W: Write>(
+        &self,
+        buf: &mut W,
+        host_os: HostOs,
+    ) -> Result<(), Error> {
+        let file_comment = self
+            .header
+            .file_comment
+            .as_deref()
+            .unwrap_or_default()
+            .as_bytes();
+        let file_comment_len =
+            u16::try_from(file_comment.len()).map_err(|_| Error::CommentTooLong)?;
+        let zip64_values = Self::central_zip64_values(
+            self.header.uncompressed_size,
+            self.compressed_size,
+            self.local_header_offset,
+        );
+        let zip64 = self.zip64 || zip64_values.count > 0;
+        let extra_field_len =
+            u16::try_from(self.header.extra_fields.data_length::<true>() + zip64_values.len)
+                .map_err(|_| Error::ExtraFieldTooLong(self.header.filename.clone()))?;
 
-    pub fn write_central_directory_entry<W: Write>(&self, buf: &mut W) -> std::io::Result<()> {
         // Writing to a temporary in-memory statically sized array first
         let mut central_dir_entry_header = [0; Self::CENTRAL_DIR_ENTRY_LEN];
         {
@@ -185,60 +820,64 @@ impl ZipFileNoData {
             // signature
             central_dir_entry_buf.write_all(&CENTRAL_FILE_HEADER_SIGNATURE.to_le_bytes())?;
             // version made by
-            central_dir_entry_buf.write_all(&VERSION_MADE_BY.to_le_bytes())?;
+            central_dir_entry_buf.write_all(
+                &version_made_by(self.header.host_os.unwrap_or(host_os)).to_le_bytes(),
+            )?;
             // version needed to extract
-            central_dir_entry_buf.write_all(&VERSION_NEEDED_TO_EXTRACT.to_le_bytes())?;
+            central_dir_entry_buf.write_all(&self.header.version_needed(zip64).to_le_bytes())?;
             // general purpose bit flag
-            central_dir_entry_buf.write_all(&GENERAL_PURPOSE_BIT_FLAG.to_le_bytes())?;
+            central_dir_entry_buf.write_all(&self.header.general_purpose_flag().to_le_bytes())?;
             // compression type
             central_dir_entry_buf
                 .write_all(&(self.header.compression_type as u16).to_le_bytes())?;
-            // Last modification time // moved to extra fields
-            central_dir_entry_buf.write_all(&0_u16.to_le_bytes())?;
-            // Last modification date // moved to extra fields
-            central_dir_entry_buf.write_all(&0_u16.to_le_bytes())?;
+            let (dos_time, dos_date) = self.header.dos_datetime();
+            // Last modification time
+            central_dir_entry_buf.write_all(&dos_time.to_le_bytes())?;
+            // Last modification date
+            central_dir_entry_buf.write_all(&dos_date.to_le_bytes())?;
             // crc
             central_dir_entry_buf.write_all(&self.header.crc.to_le_bytes())?;
             // Compressed size
-            central_dir_entry_buf.write_all(&self.compressed_size.to_le_bytes())?;
+            central_dir_entry_buf.write_all(&header_u32(self.compressed_size).to_le_bytes())?;
             // Uncompressed size
-            central_dir_entry_buf.write_all(&self.header.uncompressed_size.to_le_bytes())?;
+            central_dir_entry_buf
+                .write_all(&header_u32(self.header.uncompressed_size).to_le_bytes())?;
             // Filename size
-            debug_assert!(self.header.filename.len() <= u16::MAX as usize);
-            central_dir_entry_buf.write_all(&(self.header.filename.len() as u16).to_le_bytes())?;
+            let filename_len = u16::try_from(self.header.filename_bytes().len())
+                .map_err(|_| Error::FilenameTooLong(self.header.filename.clone()))?;
+            central_dir_entry_buf.write_all(&filename_len.to_le_bytes())?;
             // extra field size
-            central_dir_entry_buf
-                .write_all(&self.header.extra_fields.data_length::<true>().to_le_bytes())?;
+            central_dir_entry_buf.write_all(&extra_field_len.to_le_bytes())?;
             // comment size
-            central_dir_entry_buf.write_all(
-                &(self
-                    .header
-                    .file_comment
-                    .as_ref()
-                    .map(|fc| fc.len())
-                    .unwrap_or(0) as u16)
-                    .to_le_bytes(),
-            )?;
+            central_dir_entry_buf.write_all(&file_comment_len.to_le_bytes())?;
             // disk number start
-            central_dir_entry_buf.write_all(&0_u16.to_le_bytes())?;
+            central_dir_entry_buf.write_all(&self.disk_number_start.to_le_bytes())?;
             // internal file attributes
-            central_dir_entry_buf.write_all(&0_u16.to_le_bytes())?;
+            central_dir_entry_buf.write_all(&self.header.internal_file_attributes.to_le_bytes())?;
             // external file attributes
             central_dir_entry_buf.write_all(&self.header.external_file_attributes.to_le_bytes())?;
             // relative offset of local header
-            central_dir_entry_buf.write_all(&self.local_header_offset.to_le_bytes())?;
+            central_dir_entry_buf.write_all(&header_u32(self.local_header_offset).to_le_bytes())?;
         }
 
         buf.write_all(&central_dir_entry_header)?;
 
         // Filename
-        buf.write_all(self.header.filename.as_bytes())?;
+        buf.write_all(self.header.filename_bytes())?;
+        // ZIP64 extra field
+        if zip64_values.count > 0 {
+            // Header ID
+            buf.write_all(&ZIP64_EXTRA_FIELD_ID.to_le_bytes())?;
+            // Field data size
+            buf.write_all(&((8 * zip64_values.count) as u16).to_le_bytes())?;
+            for value in &zip64_values.values[..zip64_values.count] {
+                buf.write_all(&value.to_le_bytes())?;
+            }
+        }
         // Extra field
         self.header.extra_fields.write::<_, true>(buf)?;
         // File comment
-        if let Some(file_comment) = &self.header.file_comment {
-            buf.write_all(file_comment.as_bytes())?;
-        }
+        buf.write_all(file_comment)?;
 
         Ok(())
     }
@@ -0,0 +1,60 @@
+use std::io::Write;
+
+use flate2::write::DeflateEncoder;
+
+use super::file::ZipFile;
+use crate::{CompressionType, Error};
+
+/// "Unknown" operating system in the gzip header
+const GZIP_OS_UNKNOWN: u8 = 255;
+
+/// Write a compressed file as a single gzip member. Stored data is deflated first, since gzip
+/// only supports deflate.
+pub fn write_gzip_member<W: Write>(file: &ZipFile, writer: &mut W) -> std::io::Result<()> {
+    // Deflate64 data can't be converted to deflate without decompressing it
+    if matches!(
+        file.header.compression_type,
+        CompressionType::Deflate64 | CompressionType::Default
+    ) {
+        return Err(Error::UnsupportedCompression(file.header.compression_type).into());
+    }
+
+    // Gzip mtime is unsigned, 0 means that there is no timestamp
+    let mtime = file
+        .header
+        .modification_time
+        .and_then(|time| u32::try_from(time).ok())
+        .unwrap_or(0);
+
+    let mut header = [0; 10];
+    // Magic number
+    header[0..2].copy_from_slice(&[0x1f, 0x8b]);
+    // Compression method, deflate
+    header[2] = 8;
+    // Flags, none
+    header[3] = 0;
+    // Modification time
+    header[4..8].copy_from_slice(&mtime.to_le_bytes());
+    // Extra flags, compression level is unknown
+    header[8] = 0;
+    // Operating system
+    header[9] = GZIP_OS_UNKNOWN;
+    writer.write_all(&header)?;
+
+    match file.header.compression_type {
+        CompressionType::Deflate => writer.write_all(&file.data)?,
+        CompressionType::Stored => {
+            let mut encoder =
+                DeflateEncoder::new(&mut *writer, file.header.compression_level.into());
+            encoder.write_all(&file.data)?;
+            encoder.finish()?;
+        }
+        CompressionType::Deflate64 | CompressionType::Default => unreachable!(),
+    }
+
+    // CRC-32 of the uncompressed data
+    writer.write_all(&file.header.crc.to_le_bytes())?;
+    // Uncompressed size modulo 2^32
+    writer.write_all(&(file.header.uncompressed_size as u32).to_le_bytes())?;
+    Ok(())
+}
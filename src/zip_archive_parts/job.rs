@@ -1,17 +1,37 @@
 use std::{
     borrow::Cow,
     fs::{File, Metadata},
-    io::Read,
+    io::{Cursor, Read, Seek, Write},
     panic::{RefUnwindSafe, UnwindSafe},
     path::Path,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use cfg_if::cfg_if;
 use derivative::Derivative;
-use flate2::{read::DeflateEncoder, CrcReader};
+use flate2::{
+    read::{DeflateDecoder, DeflateEncoder},
+    CrcReader,
+};
 
-use super::{extra_field::ExtraFields, file::ZipFile};
-use crate::{level::CompressionLevel, zip_archive_parts::file::ZipFileHeader, CompressionType};
+use super::{
+    deflate::deflate_with_strategy,
+    extra_field::{ExtraField, ExtraFields},
+    file::{ZipFile, ZipFileNoData},
+    zipcrypto,
+};
+use crate::{
+    level::CompressionLevel, zip_archive_parts::file::ZipFileHeader, CompressionSelector,
+    CompressionType, DeflateStrategy, Error, HostOs,
+};
+
+/// Seconds since UNIX epoch, negative for times before it
+pub(crate) fn system_time_to_secs(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since_epoch) => since_epoch.as_secs() as i64,
+        Err(before_epoch) => -(before_epoch.duration().as_secs() as i64),
+    }
+}
 
 #[derive(Derivative)]
 #[derivative(Debug)]
@@ -21,30 +41,185 @@ pub enum ZipJobOrigin<'d, 'p, 'r> {
         path: Cow<'p, Path>,
     },
     RawData(Cow<'d, [u8]>),
-    Reader(
+    Reader {
+        #[derivative(Debug = "ignore")]
+        reader: Box<dyn Read + Send + Sync + UnwindSafe + RefUnwindSafe + 'r>,
+        /// Amount of data in the reader, if known upfront
+        len: Option<u64>,
+    },
+    /// Data that is compressed already and is written as-is
+    Precompressed {
         #[derivative(Debug = "ignore")]
-        Box<dyn Read + Send + Sync + UnwindSafe + RefUnwindSafe + 'r>,
-    ),
+        data: Vec<u8>,
+        crc: u32,
+        uncompressed_size: u64,
+        compression_type: CompressionType,
+    },
+}
+
+/// How the text bit of the internal file attributes is set
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TextFlag {
+    #[default]
+    Binary,
+    Text,
+    /// Text if there are no NUL bytes at the start of the data
+    Detect,
+}
+
+impl TextFlag {
+    #[inline]
+    fn internal_attributes(self, looks_like_text: bool) -> u16 {
+        match self {
+            Self::Binary => 0,
+            Self::Text => 1,
+            Self::Detect => looks_like_text as u16,
+        }
+    }
+}
+
+/// Checks the start of the data passing through for NUL bytes, which text files don't have
+struct TextDetector<R> {
+    inner: R,
+    inspected: usize,
+    has_nul: bool,
+}
+
+impl<R> TextDetector<R> {
+    /// Same amount of data as Info-Zip checks
+    const INSPECTED_LEN: usize = 8192;
+
+    #[inline]
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            inspected: 0,
+            has_nul: false,
+        }
+    }
+
+    #[inline]
+    fn looks_like_text(&self) -> bool {
+        !self.has_nul
+    }
+}
+
+impl<R: Read> Read for TextDetector<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        let to_inspect = read.min(Self::INSPECTED_LEN - self.inspected);
+        self.has_nul |= buf[..to_inspect].contains(&0);
+        self.inspected += to_inspect;
+        Ok(read)
+    }
+}
+
+/// Computes the crc of the data read through it, unless the crc is known already
+enum ChecksumReader<R> {
+    Computed(CrcReader<R>),
+    Known { inner: R, crc: u32 },
+}
+
+impl<R: Read> ChecksumReader<R> {
+    #[inline]
+    fn new(inner: R, known_crc: Option<u32>) -> Self {
+        match known_crc {
+            Some(crc) => Self::Known { inner, crc },
+            None => Self::Computed(CrcReader::new(inner)),
+        }
+    }
+
+    #[inline]
+    fn crc(&self) -> u32 {
+        match self {
+            Self::Computed(crc_reader) => crc_reader.crc().sum(),
+            Self::Known { crc, .. } => *crc,
+        }
+    }
+
+    #[inline]
+    fn get_ref(&self) -> &R {
+        match self {
+            Self::Computed(crc_reader) => crc_reader.get_ref(),
+            Self::Known { inner, .. } => inner,
+        }
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Computed(crc_reader) => crc_reader.read(buf),
+            Self::Known { inner, .. } => inner.read(buf),
+        }
+    }
 }
 
 #[derive(Debug)]
 struct FileDigest {
     data: Vec<u8>,
-    uncompressed_size: u32,
+    uncompressed_size: u64,
     crc: u32,
+    looks_like_text: bool,
+    /// Can differ from the requested one if the data was stored as-is after all
+    compression_type: CompressionType,
+    /// Data was stored because compressing it didn't make it smaller, empty data doesn't count
+    stored_fallback: bool,
 }
 
-#[derive(Debug)]
+#[derive(Derivative)]
+#[derivative(Debug)]
 pub struct ZipJob<'a, 'p, 'r> {
     pub data_origin: ZipJobOrigin<'a, 'p, 'r>,
     pub extra_fields: ExtraFields,
     pub archive_path: String,
+    /// Written to the headers instead of `archive_path`, without the UTF-8 flag
+    pub raw_archive_path: Option<Vec<u8>>,
+    /// Overrides the host OS of the archive for this entry
+    pub host_os: Option<HostOs>,
     pub file_comment: Option<String>,
+    /// Seconds since UNIX epoch. Filesystem entries use the modification time from the metadata
+    /// instead.
+    pub modification_time: Option<i64>,
+    /// Add [`ExtraField::UnicodePath`] with the file name
+    pub unicode_path: bool,
+    /// Alignment of the file data in the archive
+    pub alignment: Option<u16>,
+    /// Store the data as-is if compressing it makes it larger
+    pub fallback_to_stored: bool,
+    /// Check the CRC-32 against the data once it's compressed
+    pub verify_crc: bool,
+    pub text: TextFlag,
+    /// Compress directly into the output when writing, instead of into memory first
+    pub streamed: bool,
+    /// Write the checksum and sizes of a streamed file in a data descriptor after the data,
+    /// instead of seeking back to the local header
+    pub data_descriptor: bool,
+    /// Remove timestamps of a streamed file before writing its header
+    pub strip_timestamps: bool,
+    /// Encrypt the data with traditional PKWARE encryption
+    #[derivative(Debug = "ignore")]
+    pub password: Option<Vec<u8>>,
+    /// High 16 bits of the external file attributes
     pub external_attributes: u16,
+    /// Low 16 bits of the external file attributes, used for MS-DOS attributes
+    pub dos_attributes: u16,
     /// Ignored when [`data_origin`](Self::data_origin) is a [`ZipJobOrigin::Directory`]
     pub compression_level: CompressionLevel,
     /// Ignored when [`data_origin`](Self::data_origin) is a [`ZipJobOrigin::Directory`]
     pub compression_type: CompressionType,
+    /// Chooses the compression type when [`compression_type`](Self::compression_type) is
+    /// [`CompressionType::Default`]
+    #[derivative(Debug = "ignore")]
+    pub compression_selector: Option<CompressionSelector>,
+    pub deflate_strategy: DeflateStrategy,
+    /// CRC-32 of the uncompressed data, used instead of computing it
+    pub known_crc: Option<u32>,
+    /// Additional bits of the general purpose bit flag
+    pub general_purpose_flags: u16,
+    /// Upper limit of the memory allocated upfront for compressing the file
+    pub max_preallocation: Option<usize>,
 }
 
 impl ZipJob<'_, '_, '_> {
@@ -76,126 +251,465 @@ impl ZipJob<'_, '_, '_> {
         }
     }
 
+    /// Open a file that was added from the filesystem. Errors include both paths, so that the
+    /// file can be told apart from the others.
+    fn open_from_fs(path: &Path, archive_path: &str) -> Result<(File, Metadata), Error> {
+        let open = || {
+            let file = File::open(path)?;
+            let metadata = file.metadata()?;
+            Ok((file, metadata))
+        };
+        open().map_err(|source| Error::OpenInput {
+            archive_path: archive_path.to_owned(),
+            fs_path: path.to_owned(),
+            source,
+        })
+    }
+
+    /// Open the file of a job added from the filesystem and read from it from now on, so that a
+    /// missing or unreadable file is reported while the job can still be kept.
+    pub(crate) fn open_fs_source(&mut self) -> Result<(), Error> {
+        if let ZipJobOrigin::Filesystem { path } = &self.data_origin {
+            let (file, file_metadata) = Self::open_from_fs(path, &self.archive_path)?;
+            let len = file_metadata.is_file().then_some(file_metadata.len());
+            self.apply_fs_metadata(&file_metadata);
+            self.data_origin = ZipJobOrigin::Reader {
+                reader: Box::new(file),
+                len,
+            };
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub(crate) fn modification_time_from_fs(metadata: &Metadata) -> Option<i64> {
+        metadata.modified().ok().map(system_time_to_secs)
+    }
+
+    /// Compress the data from `source` using `buffer`, which must be empty, as scratch space.
+    /// Only the final data is copied out of it, so that the allocation can be reused for the next
+    /// file.
     fn compress_file<R: Read>(
         source: R,
-        uncompressed_size_approx: Option<u32>,
+        buffer: &mut Vec<u8>,
         compression_type: CompressionType,
         compression_level: CompressionLevel,
+        deflate_strategy: DeflateStrategy,
+        fallback_to_stored: bool,
+        known_crc: Option<u32>,
     ) -> std::io::Result<FileDigest> {
-        let mut crc_reader = CrcReader::new(source);
-        let mut data = Vec::with_capacity(uncompressed_size_approx.unwrap_or(0) as usize);
+        debug_assert!(buffer.is_empty());
+        let mut crc_reader = ChecksumReader::new(TextDetector::new(source), known_crc);
+        let data = buffer;
         let uncompressed_size = match compression_type {
+            CompressionType::Deflate if deflate_strategy != DeflateStrategy::Default => {
+                deflate_with_strategy(&mut crc_reader, data, compression_level, deflate_strategy)?
+            }
             CompressionType::Deflate => {
                 let mut encoder = DeflateEncoder::new(&mut crc_reader, compression_level.into());
-                encoder.read_to_end(&mut data)?;
-                encoder.total_in() as usize
+                encoder.read_to_end(data)?;
+                encoder.total_in()
+            }
+            CompressionType::Stored => crc_reader.read_to_end(data)? as u64,
+            CompressionType::Deflate64 | CompressionType::Default => {
+                return Err(Error::UnsupportedCompression(compression_type).into())
             }
-            CompressionType::Stored => crc_reader.read_to_end(&mut data)?,
         };
-        debug_assert!(uncompressed_size <= u32::MAX as usize);
-        let uncompressed_size = uncompressed_size as u32;
-        data.shrink_to_fit();
-        let crc = crc_reader.crc().sum();
+        let crc = crc_reader.crc();
+        let looks_like_text = crc_reader.get_ref().looks_like_text();
+
+        // Decompressing is cheaper than keeping a copy of the uncompressed data around for the
+        // rare case of compression not helping. Empty files are always stored, compressing
+        // nothing only adds overhead.
+        if (fallback_to_stored || uncompressed_size == 0)
+            && compression_type == CompressionType::Deflate
+            && data.len() as u64 >= uncompressed_size
+        {
+            let mut stored = Vec::with_capacity(uncompressed_size as usize);
+            DeflateDecoder::new(data.as_slice()).read_to_end(&mut stored)?;
+            return Ok(FileDigest {
+                data: stored,
+                uncompressed_size,
+                crc,
+                looks_like_text,
+                compression_type: CompressionType::Stored,
+                stored_fallback: uncompressed_size != 0,
+            });
+        }
+
+        // Large buffers are not worth keeping around, they are handed off without copying instead
+        let data = if data.capacity() > Self::MAX_REUSED_BUFFER_CAPACITY {
+            let mut data = std::mem::take(data);
+            data.shrink_to_fit();
+            data
+        } else {
+            data.as_slice().to_vec()
+        };
         Ok(FileDigest {
             data,
             uncompressed_size,
             crc,
+            looks_like_text,
+            compression_type,
+            stored_fallback: false,
         })
     }
 
-    pub fn into_file(self) -> std::io::Result<ZipFile> {
-        match self.data_origin {
-            ZipJobOrigin::Directory => Ok(ZipFile::directory(
-                self.archive_path,
-                self.extra_fields,
-                self.external_attributes,
-                self.file_comment,
-            )),
+    /// Compress the data from `source` into `buf`, returning the crc and the uncompressed size.
+    /// Whether the data looks like text is stored in `looks_like_text`.
+    fn compress_file_into<R: Read, W: Write + ?Sized>(
+        source: R,
+        buf: &mut W,
+        compression_type: CompressionType,
+        compression_level: CompressionLevel,
+        deflate_strategy: DeflateStrategy,
+        known_crc: Option<u32>,
+        looks_like_text: &mut bool,
+    ) -> std::io::Result<(u32, u64)> {
+        let mut crc_reader = ChecksumReader::new(TextDetector::new(source), known_crc);
+        let uncompressed_size = match compression_type {
+            CompressionType::Deflate if deflate_strategy != DeflateStrategy::Default => {
+                deflate_with_strategy(&mut crc_reader, buf, compression_level, deflate_strategy)?
+            }
+            CompressionType::Deflate => {
+                let mut encoder = DeflateEncoder::new(&mut crc_reader, compression_level.into());
+                std::io::copy(&mut encoder, buf)?;
+                encoder.total_in()
+            }
+            CompressionType::Stored => std::io::copy(&mut crc_reader, buf)?,
+            CompressionType::Deflate64 | CompressionType::Default => {
+                return Err(Error::UnsupportedCompression(compression_type).into())
+            }
+        };
+        *looks_like_text = crc_reader.get_ref().looks_like_text();
+        Ok((crc_reader.crc(), uncompressed_size))
+    }
+
+    /// Add an extended timestamp extra field with the modification time, unless there is a
+    /// timestamp extra field already. The field is omitted if the time doesn't fit into it.
+    fn add_timestamp_extra_field(&mut self) {
+        let Some(mod_time) = self.modification_time.and_then(|t| i32::try_from(t).ok()) else {
+            return;
+        };
+        let has_timestamp = self
+            .extra_fields
+            .values
+            .iter()
+            .any(ExtraField::is_timestamp);
+        if !has_timestamp {
+            self.extra_fields
+                .values
+                .push(ExtraField::UnixExtendedTimestamp {
+                    mod_time: Some(mod_time),
+                    ac_time: None,
+                    cr_time: None,
+                });
+        }
+    }
+
+    /// Approximate length of the local header together with the data, and of the central
+    /// directory entry. The data is counted uncompressed if its size is known without reading it,
+    /// and extra fields that are only added when compressing are not counted.
+    pub(crate) fn estimated_len(&self) -> (u64, u64) {
+        let data_len = match &self.data_origin {
+            ZipJobOrigin::Directory => 0,
             ZipJobOrigin::Filesystem { path } => {
-                let file = File::open(path).unwrap();
-                let file_metadata = file.metadata().unwrap();
-                let uncompressed_size_approx = file_metadata.len();
-                debug_assert!(uncompressed_size_approx <= u32::MAX.into());
-                let uncompressed_size_approx = uncompressed_size_approx as u32;
-                let external_file_attributes = Self::attributes_from_fs(&file_metadata);
-                let mut extra_fields = ExtraFields::new_from_fs(&file_metadata);
-                extra_fields.extend(self.extra_fields);
-
-                let FileDigest {
-                    data,
-                    uncompressed_size,
-                    crc,
-                } = Self::compress_file(
-                    file,
-                    Some(uncompressed_size_approx),
-                    self.compression_type,
-                    self.compression_level,
-                )?;
-                Ok(ZipFile {
-                    header: ZipFileHeader {
-                        compression_type: CompressionType::Deflate,
-                        crc,
-                        uncompressed_size,
-                        filename: self.archive_path,
-                        external_file_attributes: (external_file_attributes as u32) << 16,
-                        extra_fields,
-                        file_comment: self.file_comment,
-                    },
-                    data,
-                })
+                std::fs::metadata(path).map_or(0, |metadata| metadata.len())
             }
-            ZipJobOrigin::RawData(data) => {
-                let uncompressed_size_approx = data.len();
-                debug_assert!(uncompressed_size_approx <= u32::MAX as usize);
-                let uncompressed_size_approx = uncompressed_size_approx as u32;
+            ZipJobOrigin::RawData(data) => data.len() as u64,
+            ZipJobOrigin::Reader { len, .. } => len.unwrap_or(0),
+            ZipJobOrigin::Precompressed { data, .. } => data.len() as u64,
+        };
+        let data_descriptor_len = if self.streamed && self.data_descriptor {
+            ZipFile::DATA_DESCRIPTOR_LEN
+        } else {
+            0
+        };
+        let filename_len = self
+            .raw_archive_path
+            .as_ref()
+            .map_or(self.archive_path.len(), Vec::len);
+        let file_comment_len = self.file_comment.as_ref().map_or(0, String::len);
+        let local_len = ZipFile::LOCAL_FILE_HEADER_LEN
+            + filename_len
+            + self.extra_fields.data_length::<false>()
+            + data_descriptor_len;
+        let central_len = ZipFileNoData::CENTRAL_DIR_ENTRY_LEN
+            + filename_len
+            + self.extra_fields.data_length::<true>()
+            + file_comment_len;
+        (local_len as u64 + data_len, central_len as u64)
+    }
 
-                let FileDigest {
-                    data,
-                    uncompressed_size,
-                    crc,
-                } = Self::compress_file(
-                    data.as_ref(),
-                    Some(uncompressed_size_approx),
-                    self.compression_type,
-                    self.compression_level,
-                )?;
-                Ok(ZipFile {
-                    header: ZipFileHeader {
-                        compression_type: CompressionType::Deflate,
-                        crc,
-                        uncompressed_size,
-                        filename: self.archive_path,
-                        external_file_attributes: (self.external_attributes as u32) << 16,
-                        extra_fields: self.extra_fields,
-                        file_comment: self.file_comment,
-                    },
-                    data,
-                })
+    /// How much memory to allocate upfront for compressing data of approximately this size
+    #[inline]
+    fn preallocation(uncompressed_size_approx: u64, max_preallocation: Option<usize>) -> usize {
+        let size = usize::try_from(uncompressed_size_approx).unwrap_or(usize::MAX);
+        max_preallocation.map_or(size, |max| size.min(max))
+    }
+
+    /// Largest scratch buffer that is kept for compressing the next file
+    const MAX_REUSED_BUFFER_CAPACITY: usize = 1024 * 1024;
+
+    #[inline]
+    /// Replace [`CompressionType::Default`] with the type chosen by the selector for the path of
+    /// this file. A selector that returns [`CompressionType::Default`] itself, or no selector at
+    /// all, means [`CompressionType::Deflate`].
+    pub fn resolve_compression_type(&mut self) {
+        if self.compression_type != CompressionType::Default {
+            return;
+        }
+        let selected = self
+            .compression_selector
+            .as_ref()
+            .map(|selector| selector(&self.archive_path));
+        self.compression_type = match selected {
+            Some(CompressionType::Default) | None => CompressionType::Deflate,
+            Some(compression_type) => compression_type,
+        };
+    }
+
+    pub fn into_file(self) -> std::io::Result<ZipFile> {
+        self.into_file_with_buffer(&mut Vec::new())
+    }
+
+    /// Same as [`into_file`](Self::into_file), but uses `buffer` as scratch space for
+    /// compression, so that a worker compressing many files doesn't allocate for every one.
+    pub fn into_file_with_buffer(mut self, buffer: &mut Vec<u8>) -> std::io::Result<ZipFile> {
+        self.resolve_compression_type();
+        // Filesystem entries get their timestamps from the metadata
+        if !matches!(self.data_origin, ZipJobOrigin::Filesystem { .. }) {
+            self.add_timestamp_extra_field();
+        }
+        let unicode_path = self.unicode_path;
+        let verify_crc = self.verify_crc;
+        let password = match self.data_origin {
+            ZipJobOrigin::Directory => None,
+            _ => self.password.take(),
+        };
+        let mut file = self.compress_into_file(buffer)?;
+        if verify_crc {
+            file.verify_crc()?;
+        }
+        if let Some(password) = password {
+            // There is no data descriptor, so the check byte is the high byte of the crc
+            let check_byte = (file.header.crc >> 24) as u8;
+            file.data = zipcrypto::encrypt(&file.data, &password, check_byte);
+            file.header.encrypted = true;
+        }
+        // Directory names can be changed when creating the file, so the final name is used
+        if unicode_path {
+            let unicode_path_field = ExtraField::unicode_path_with_header_name(
+                &file.header.filename,
+                file.header.filename_bytes(),
+            );
+            file.header.extra_fields.values.push(unicode_path_field);
+        }
+        Ok(file)
+    }
+
+    /// Compress the file directly into `buf` instead of into memory, so that only the compressor
+    /// state is kept in memory regardless of the file size. The header is patched afterwards,
+    /// which requires seeking, unless the job uses a [data descriptor](Self::data_descriptor).
+    pub fn write_streamed<W: Write + Seek>(
+        mut self,
+        buf: &mut W,
+    ) -> std::io::Result<ZipFileNoData> {
+        self.resolve_compression_type();
+        // Empty data is stored, and stored data must not be followed by a data descriptor, since
+        // readers that go through local headers couldn't tell where it ends
+        let empty_with_descriptor = self.data_descriptor
+            && matches!(&self.data_origin, ZipJobOrigin::RawData(data) if data.is_empty());
+        if empty_with_descriptor
+            || matches!(
+                self.data_origin,
+                ZipJobOrigin::Directory | ZipJobOrigin::Precompressed { .. }
+            )
+        {
+            let strip_timestamps = self.strip_timestamps;
+            let mut file = self.into_file()?;
+            if strip_timestamps {
+                file.strip_timestamps();
             }
-            ZipJobOrigin::Reader(reader) => {
-                let FileDigest {
+            return file.write_local_file_header_with_data_consuming(buf);
+        }
+        // Filesystem entries get their timestamps from the metadata
+        if !matches!(self.data_origin, ZipJobOrigin::Filesystem { .. }) {
+            self.add_timestamp_extra_field();
+        }
+        // Size of the other sources is not known before they are compressed
+        if matches!(&self.data_origin, ZipJobOrigin::RawData(data) if data.is_empty()) {
+            self.compression_type = CompressionType::Stored;
+        }
+
+        let (source, size_hint): (Box<dyn Read + '_>, Option<u64>) =
+            match std::mem::replace(&mut self.data_origin, ZipJobOrigin::Directory) {
+                ZipJobOrigin::Directory | ZipJobOrigin::Precompressed { .. } => unreachable!(),
+                ZipJobOrigin::Filesystem { path } => {
+                    let (file, file_metadata) = Self::open_from_fs(&path, &self.archive_path)?;
+                    self.apply_fs_metadata(&file_metadata);
+                    (Box::new(file), Some(file_metadata.len()))
+                }
+                ZipJobOrigin::RawData(data) => {
+                    let len = data.len() as u64;
+                    (Box::new(Cursor::new(data)), Some(len))
+                }
+                ZipJobOrigin::Reader { reader, len } => (reader, len),
+            };
+        // The sizes go into the ZIP64 extra field if they could reach 4 GiB, leaving a margin for
+        // data that grows when compressed
+        let reserve_zip64 =
+            size_hint.is_some_and(|size| size >= u64::from(u32::MAX - (u32::MAX >> 8)));
+        let mut header = self.header(self.compression_type, 0, 0);
+        if self.strip_timestamps {
+            header.strip_timestamps();
+        }
+        if self.unicode_path {
+            let unicode_path_field = ExtraField::unicode_path_with_header_name(
+                &header.filename,
+                header.filename_bytes(),
+            );
+            header.extra_fields.values.push(unicode_path_field);
+        }
+
+        let (compression_type, compression_level, deflate_strategy, known_crc) = (
+            self.compression_type,
+            self.compression_level,
+            self.deflate_strategy,
+            self.known_crc,
+        );
+        let mut looks_like_text = false;
+        let write_data = |buf: &mut W| {
+            Self::compress_file_into(
+                source,
+                buf,
+                compression_type,
+                compression_level,
+                deflate_strategy,
+                known_crc,
+                &mut looks_like_text,
+            )
+        };
+        let file = ZipFile {
+            header,
+            data: Vec::new(),
+        };
+        let mut file = if self.data_descriptor {
+            file.write_local_file_header_with_data_descriptor(buf, reserve_zip64, write_data)?
+        } else {
+            file.write_local_file_header_with_streamed_data(buf, reserve_zip64, write_data)?
+        };
+        file.header.internal_file_attributes = self.text.internal_attributes(looks_like_text);
+        Ok(file)
+    }
+
+    /// Header with the properties of this job, for data compressed with `compression_type`
+    /// that has this checksum and size. The path, extra fields and comment are moved out of the
+    /// job.
+    fn header(
+        &mut self,
+        compression_type: CompressionType,
+        crc: u32,
+        uncompressed_size: u64,
+    ) -> ZipFileHeader {
+        ZipFileHeader {
+            compression_type,
+            crc,
+            uncompressed_size,
+            filename: std::mem::take(&mut self.archive_path),
+            raw_filename: self.raw_archive_path.take(),
+            host_os: self.host_os,
+            external_file_attributes: ZipFile::external_file_attributes(
+                self.external_attributes,
+                self.dos_attributes,
+            ),
+            internal_file_attributes: 0,
+            extra_fields: std::mem::take(&mut self.extra_fields),
+            file_comment: self.file_comment.take(),
+            modification_time: self.modification_time,
+            alignment: self.alignment,
+            encrypted: false,
+            data_descriptor: false,
+            stored_fallback: false,
+            compression_level: self.compression_level,
+            extra_flags: self.general_purpose_flags,
+        }
+    }
+
+    /// Use the attributes, modification time and extra fields of a file added from the
+    /// filesystem. Extra fields of the job are kept after the ones from the metadata.
+    fn apply_fs_metadata(&mut self, metadata: &Metadata) {
+        self.external_attributes = Self::attributes_from_fs(metadata);
+        self.modification_time = Self::modification_time_from_fs(metadata);
+        let mut extra_fields = ExtraFields::new_from_fs(metadata);
+        extra_fields.extend(std::mem::take(&mut self.extra_fields));
+        self.extra_fields = extra_fields;
+    }
+
+    fn compress_into_file(mut self, buffer: &mut Vec<u8>) -> std::io::Result<ZipFile> {
+        buffer.clear();
+        let (source, uncompressed_size_approx): (Box<dyn Read + '_>, u64) =
+            match std::mem::replace(&mut self.data_origin, ZipJobOrigin::Directory) {
+                ZipJobOrigin::Directory => {
+                    let header = self.header(CompressionType::Stored, 0, 0);
+                    return Ok(ZipFile::new(header, Vec::new()));
+                }
+                ZipJobOrigin::Precompressed {
                     data,
-                    uncompressed_size,
                     crc,
-                } = Self::compress_file(
-                    reader,
-                    None,
-                    self.compression_type,
-                    self.compression_level,
-                )?;
-                Ok(ZipFile {
-                    header: ZipFileHeader {
-                        compression_type: CompressionType::Deflate,
-                        crc,
-                        uncompressed_size,
-                        filename: self.archive_path,
-                        external_file_attributes: (self.external_attributes as u32) << 16,
-                        extra_fields: self.extra_fields,
-                        file_comment: self.file_comment,
-                    },
-                    data,
-                })
-            }
-        }
+                    uncompressed_size,
+                    compression_type,
+                } => {
+                    if compression_type == CompressionType::Default {
+                        return Err(Error::UnsupportedCompression(compression_type).into());
+                    }
+                    let mut header = self.header(compression_type, crc, uncompressed_size);
+                    header.internal_file_attributes = self.text.internal_attributes(false);
+                    return Ok(ZipFile::new(header, data));
+                }
+                ZipJobOrigin::Filesystem { path } => {
+                    let (file, file_metadata) = Self::open_from_fs(&path, &self.archive_path)?;
+                    // The reported size is only a hint, the size of the data actually read is the
+                    // one written to the header. Special files like the ones in /proc, device
+                    // nodes and pipes report 0 or a size unrelated to their contents.
+                    let uncompressed_size_approx = if file_metadata.is_file() {
+                        file_metadata.len()
+                    } else {
+                        0
+                    };
+                    self.apply_fs_metadata(&file_metadata);
+                    (Box::new(file), uncompressed_size_approx)
+                }
+                ZipJobOrigin::RawData(data) => {
+                    let uncompressed_size_approx = data.len() as u64;
+                    (Box::new(Cursor::new(data)), uncompressed_size_approx)
+                }
+                ZipJobOrigin::Reader { reader, len } => (reader, len.unwrap_or(0)),
+            };
+        buffer.reserve(Self::preallocation(
+            uncompressed_size_approx,
+            self.max_preallocation,
+        ));
+
+        let FileDigest {
+            data,
+            uncompressed_size,
+            crc,
+            looks_like_text,
+            compression_type,
+            stored_fallback,
+        } = Self::compress_file(
+            source,
+            buffer,
+            self.compression_type,
+            self.compression_level,
+            self.deflate_strategy,
+            self.fallback_to_stored,
+            self.known_crc,
+        )?;
+        let mut header = self.header(compression_type, crc, uncompressed_size);
+        header.internal_file_attributes = self.text.internal_attributes(looks_like_text);
+        header.stored_fallback = stored_fallback;
+        Ok(ZipFile::new(header, data))
     }
 }
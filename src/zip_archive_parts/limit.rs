@@ -0,0 +1,128 @@
+use std::{
+    collections::BTreeMap,
+    sync::{mpsc, Condvar, Mutex},
+};
+
+use super::file::ZipFile;
+
+/// Limit on the amount of compressed data that is waiting to be written
+#[derive(Debug)]
+pub struct BufferLimit {
+    max_bytes: Option<usize>,
+    state: Mutex<BufferState>,
+    released: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct BufferState {
+    buffered_bytes: usize,
+    /// Set when the receiving side is gone, nothing will be released anymore
+    closed: bool,
+}
+
+impl BufferLimit {
+    #[inline]
+    pub fn new(max_bytes: Option<usize>) -> Self {
+        Self {
+            max_bytes,
+            state: Mutex::default(),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Block until the amount of buffered data is within the limit. Returns `false` if the
+    /// receiving side is gone.
+    pub fn wait(&self) -> bool {
+        let Some(max_bytes) = self.max_bytes else {
+            return true;
+        };
+        let state = self
+            .released
+            .wait_while(self.state.lock().unwrap(), |state| {
+                !state.closed && state.buffered_bytes > max_bytes
+            })
+            .unwrap();
+        !state.closed
+    }
+
+    /// Count a compressed file as buffered until it is received
+    pub fn acquire(&self, bytes: usize) {
+        if self.max_bytes.is_some() {
+            self.state.lock().unwrap().buffered_bytes += bytes;
+        }
+    }
+
+    fn release(&self, bytes: usize) {
+        if self.max_bytes.is_some() {
+            self.state.lock().unwrap().buffered_bytes -= bytes;
+            self.released.notify_all();
+        }
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.released.notify_all();
+    }
+}
+
+/// A compressed file along with the position of its job in the queue
+pub type IndexedFile = (usize, std::io::Result<ZipFile>);
+
+/// Files that were compressed before the ones queued ahead of them, kept until it's their turn
+#[derive(Debug, Default)]
+struct ReorderBuffer {
+    next_index: usize,
+    pending: BTreeMap<usize, std::io::Result<ZipFile>>,
+}
+
+/// Receiving side of the compressed files, releases the buffered data as files are received
+#[derive(Debug)]
+pub struct LimitedReceiver<'a> {
+    rx: mpsc::Receiver<IndexedFile>,
+    limit: &'a BufferLimit,
+    /// Set if the files have to be received in the order of their jobs
+    reorder: Option<ReorderBuffer>,
+}
+
+impl<'a> LimitedReceiver<'a> {
+    #[inline]
+    pub fn new(rx: mpsc::Receiver<IndexedFile>, limit: &'a BufferLimit, ordered: bool) -> Self {
+        Self {
+            rx,
+            limit,
+            reorder: ordered.then(ReorderBuffer::default),
+        }
+    }
+}
+
+impl Iterator for LimitedReceiver<'_> {
+    type Item = std::io::Result<ZipFile>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = match &mut self.reorder {
+            None => self.rx.recv().ok()?.1,
+            Some(reorder) => loop {
+                if let Some(file) = reorder.pending.remove(&reorder.next_index) {
+                    reorder.next_index += 1;
+                    break file;
+                }
+                // Jobs are taken in order, so the missing file is still being compressed and the
+                // channel can only be closed early if a worker panicked
+                let (index, file) = self.rx.recv().ok()?;
+                reorder.pending.insert(index, file);
+            },
+        };
+        // Files waiting for their turn stay counted as buffered
+        if let Ok(file) = &item {
+            self.limit.release(file.data.len());
+        }
+        Some(item)
+    }
+}
+
+impl Drop for LimitedReceiver<'_> {
+    fn drop(&mut self) {
+        // Workers waiting for the buffered data to be released would wait forever otherwise
+        self.limit.close();
+    }
+}
@@ -0,0 +1,53 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use super::job::ZipJob;
+
+/// Job together with its position in the original queue
+pub type IndexedJob<'d, 'p, 'r> = (usize, ZipJob<'d, 'p, 'r>);
+
+/// Jobs split into one queue per worker, so that workers compressing many small files don't all
+/// contend for the same lock. A worker whose queue ran out steals from the back of the others.
+#[derive(Debug)]
+pub struct JobQueues<'d, 'p, 'r> {
+    queues: Vec<Mutex<VecDeque<IndexedJob<'d, 'p, 'r>>>>,
+}
+
+impl<'d, 'p, 'r> JobQueues<'d, 'p, 'r> {
+    /// Split `jobs` into `count` contiguous chunks of about the same length
+    pub fn new(jobs: Vec<ZipJob<'d, 'p, 'r>>, count: usize) -> Self {
+        let count = count.max(1);
+        let chunk_len = jobs.len().div_ceil(count).max(1);
+        let mut queues: Vec<_> = (0..count).map(|_| VecDeque::new()).collect();
+        for (index, job) in jobs.into_iter().enumerate() {
+            queues[index / chunk_len].push_back((index, job));
+        }
+        Self {
+            queues: queues.into_iter().map(Mutex::new).collect(),
+        }
+    }
+
+    /// Take the next job for `worker`: from the front of its own queue, or from the back of
+    /// another queue once its own is empty
+    pub fn pop(&self, worker: usize) -> Option<IndexedJob<'d, 'p, 'r>> {
+        let count = self.queues.len();
+        let own = worker % count;
+        if let Some(job) = self.queues[own].lock().unwrap().pop_front() {
+            return Some(job);
+        }
+        (1..count).find_map(|offset| {
+            self.queues[(own + offset) % count]
+                .lock()
+                .unwrap()
+                .pop_back()
+        })
+    }
+
+    /// Jobs that were not taken, in their original order
+    pub fn into_jobs(self) -> impl Iterator<Item = ZipJob<'d, 'p, 'r>> {
+        // Chunks are contiguous and only shrink from the ends, so concatenating keeps the order
+        self.queues
+            .into_iter()
+            .flat_map(|queue| queue.into_inner().unwrap())
+            .map(|(_, job)| job)
+    }
+}
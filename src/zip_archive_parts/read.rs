@@ -0,0 +1,317 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use super::{
+    data::{
+        ZipData, END_OF_CENTRAL_DIR_SIGNATURE, ZIP64_END_OF_CENTRAL_DIR_LOCATOR_SIGNATURE,
+        ZIP64_END_OF_CENTRAL_DIR_SIGNATURE,
+    },
+    extra_field::{ExtraFields, ZIP64_EXTRA_FIELD_ID},
+    file::{
+        unix_from_dos_datetime, ZipFile, ZipFileHeader, CENTRAL_FILE_HEADER_SIGNATURE,
+        LOCAL_FILE_HEADER_SIGNATURE,
+    },
+};
+use crate::{level::CompressionLevel, CompressionType, Error, HostOs};
+
+const FOOTER_LENGTH: u64 = 22;
+const ZIP64_LOCATOR_LENGTH: u64 = 20;
+const CENTRAL_DIR_ENTRY_LEN: usize = 46;
+const LOCAL_FILE_HEADER_LEN: usize = 30;
+
+/// Bit 0 of the general purpose bit flag, set for encrypted entries
+const ENCRYPTED_FLAG: u16 = 1;
+/// Bit 11 of the general purpose bit flag, set if the file name and comment are UTF-8
+const UTF8_FLAG: u16 = 1 << 11;
+
+#[inline]
+fn invalid_data(message: &'static str) -> Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message).into()
+}
+
+#[inline]
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+#[inline]
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+#[inline]
+fn u64_at(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+/// Location of the central directory as described by the end of central directory record
+struct CentralDirLocation {
+    offset: u64,
+    files_amount: u64,
+    archive_comment: Option<String>,
+}
+
+/// Metadata from a central directory entry that is needed to read the rest of the entry
+struct CentralDirEntry {
+    /// High byte of the "version made by" field
+    host_os: u8,
+    general_purpose_flag: u16,
+    compression_method: u16,
+    dos_time: u16,
+    dos_date: u16,
+    crc: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    local_header_offset: u64,
+    external_file_attributes: u32,
+    internal_file_attributes: u16,
+    filename: String,
+    /// Original file name if it's not UTF-8
+    raw_filename: Option<Vec<u8>>,
+    file_comment: Option<String>,
+}
+
+impl ZipData {
+    /// Read an existing archive. Entries keep their compressed data as-is, so they can be written
+    /// again without recompression.
+    pub fn read<R: Read + Seek>(reader: &mut R) -> Result<Self, Error> {
+        let location = Self::read_end_of_central_directory(reader)?;
+
+        reader.seek(SeekFrom::Start(location.offset))?;
+        let entries = (0..location.files_amount)
+            .map(|_| Self::read_central_directory_entry(reader))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let files = entries
+            .into_iter()
+            .map(|entry| Self::read_file(reader, entry))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            files,
+            archive_comment: location.archive_comment,
+            ..Self::default()
+        })
+    }
+
+    fn read_end_of_central_directory<R: Read + Seek>(
+        reader: &mut R,
+    ) -> Result<CentralDirLocation, Error> {
+        let archive_len = reader.seek(SeekFrom::End(0))?;
+        if archive_len < FOOTER_LENGTH {
+            return Err(invalid_data("not a zip archive"));
+        }
+
+        // The record is at the end of the archive, followed only by a comment of up to 65535 bytes
+        let tail_len = archive_len.min(FOOTER_LENGTH + u16::MAX as u64);
+        let tail_start = archive_len - tail_len;
+        let mut tail = vec![0; tail_len as usize];
+        reader.seek(SeekFrom::Start(tail_start))?;
+        reader.read_exact(&mut tail)?;
+
+        let footer_start = (0..=tail.len() - FOOTER_LENGTH as usize)
+            .rev()
+            .find(|&i| {
+                u32_at(&tail, i) == END_OF_CENTRAL_DIR_SIGNATURE
+                    && i + FOOTER_LENGTH as usize + u16_at(&tail, i + 20) as usize == tail.len()
+            })
+            .ok_or_else(|| invalid_data("end of central directory record not found"))?;
+        let footer = &tail[footer_start..];
+
+        let comment = &footer[FOOTER_LENGTH as usize..];
+        let archive_comment = if comment.is_empty() {
+            None
+        } else {
+            Some(
+                String::from_utf8(comment.to_vec())
+                    .map_err(|_| invalid_data("archive comment is not valid UTF-8"))?,
+            )
+        };
+
+        let mut files_amount = u16_at(footer, 10) as u64;
+        let mut offset = u32_at(footer, 16) as u64;
+        let mut disk_number = u16_at(footer, 4) as u32;
+        let mut disk_with_central_dir = u16_at(footer, 6) as u32;
+
+        // ZIP64 locator is right before the end of central directory record
+        let footer_offset = tail_start + footer_start as u64;
+        if footer_offset >= ZIP64_LOCATOR_LENGTH {
+            let mut locator = [0; ZIP64_LOCATOR_LENGTH as usize];
+            reader.seek(SeekFrom::Start(footer_offset - ZIP64_LOCATOR_LENGTH))?;
+            reader.read_exact(&mut locator)?;
+            if u32_at(&locator, 0) == ZIP64_END_OF_CENTRAL_DIR_LOCATOR_SIGNATURE {
+                let mut zip64_footer = [0; 56];
+                reader.seek(SeekFrom::Start(u64_at(&locator, 8)))?;
+                reader.read_exact(&mut zip64_footer)?;
+                if u32_at(&zip64_footer, 0) != ZIP64_END_OF_CENTRAL_DIR_SIGNATURE {
+                    return Err(invalid_data(
+                        "invalid zip64 end of central directory record",
+                    ));
+                }
+                files_amount = u64_at(&zip64_footer, 32);
+                offset = u64_at(&zip64_footer, 48);
+                disk_number = u32_at(&zip64_footer, 16);
+                disk_with_central_dir = u32_at(&zip64_footer, 20);
+                if u32_at(&locator, 16) != 1 {
+                    return Err(invalid_data("multi-disk archives are not supported"));
+                }
+            }
+        }
+        // Offsets are relative to the volume they are in, other volumes are separate files
+        if disk_number != 0 || disk_with_central_dir != 0 {
+            return Err(invalid_data("multi-disk archives are not supported"));
+        }
+
+        Ok(CentralDirLocation {
+            offset,
+            files_amount,
+            archive_comment,
+        })
+    }
+
+    fn read_central_directory_entry<R: Read>(reader: &mut R) -> Result<CentralDirEntry, Error> {
+        let mut header = [0; CENTRAL_DIR_ENTRY_LEN];
+        reader.read_exact(&mut header)?;
+        if u32_at(&header, 0) != CENTRAL_FILE_HEADER_SIGNATURE {
+            return Err(invalid_data("invalid central directory entry"));
+        }
+
+        let filename_len = u16_at(&header, 28) as usize;
+        let extra_field_len = u16_at(&header, 30) as usize;
+        let comment_len = u16_at(&header, 32) as usize;
+
+        let mut variable = vec![0; filename_len + extra_field_len + comment_len];
+        reader.read_exact(&mut variable)?;
+        let (filename, rest) = variable.split_at(filename_len);
+        let (extra_field, comment) = rest.split_at(extra_field_len);
+
+        let general_purpose_flag = u16_at(&header, 8);
+        // Names in other encodings are kept as-is, so that they are written back unchanged
+        let (filename, raw_filename) = match String::from_utf8(filename.to_vec()) {
+            Ok(filename) => (filename, None),
+            Err(_) if general_purpose_flag & UTF8_FLAG != 0 => {
+                return Err(invalid_data("file name is not valid UTF-8"))
+            }
+            Err(err) => {
+                let raw_filename = err.into_bytes();
+                (
+                    String::from_utf8_lossy(&raw_filename).into_owned(),
+                    Some(raw_filename),
+                )
+            }
+        };
+        let file_comment = if comment.is_empty() {
+            None
+        } else {
+            Some(
+                String::from_utf8(comment.to_vec())
+                    .map_err(|_| invalid_data("file comment is not valid UTF-8"))?,
+            )
+        };
+
+        let mut entry = CentralDirEntry {
+            host_os: header[5],
+            general_purpose_flag,
+            compression_method: u16_at(&header, 10),
+            dos_time: u16_at(&header, 12),
+            dos_date: u16_at(&header, 14),
+            crc: u32_at(&header, 16),
+            compressed_size: u32_at(&header, 20) as u64,
+            uncompressed_size: u32_at(&header, 24) as u64,
+            local_header_offset: u32_at(&header, 42) as u64,
+            external_file_attributes: u32_at(&header, 38),
+            internal_file_attributes: u16_at(&header, 36),
+            filename,
+            raw_filename,
+            file_comment,
+        };
+        entry.apply_zip64_extra_field(extra_field);
+
+        Ok(entry)
+    }
+
+    fn read_file<R: Read + Seek>(reader: &mut R, entry: CentralDirEntry) -> Result<ZipFile, Error> {
+        if entry.general_purpose_flag & ENCRYPTED_FLAG != 0 {
+            return Err(invalid_data("encrypted entries are not supported"));
+        }
+        let compression_type = CompressionType::try_from_u16(entry.compression_method)
+            .ok_or_else(|| invalid_data("unsupported compression method"))?;
+
+        let mut header = [0; LOCAL_FILE_HEADER_LEN];
+        reader.seek(SeekFrom::Start(entry.local_header_offset))?;
+        reader.read_exact(&mut header)?;
+        if u32_at(&header, 0) != LOCAL_FILE_HEADER_SIGNATURE {
+            return Err(invalid_data("invalid local file header"));
+        }
+
+        let filename_len = u16_at(&header, 26) as i64;
+        let extra_field_len = u16_at(&header, 28) as usize;
+
+        // Local header has the complete extra fields, the central directory may omit some data
+        reader.seek(SeekFrom::Current(filename_len))?;
+        let mut extra_field = vec![0; extra_field_len];
+        reader.read_exact(&mut extra_field)?;
+
+        // Reading only as much as there is, instead of allocating the size from the header upfront
+        let mut data = Vec::new();
+        reader
+            .by_ref()
+            .take(entry.compressed_size)
+            .read_to_end(&mut data)?;
+        if data.len() as u64 != entry.compressed_size {
+            return Err(invalid_data("entry data is truncated"));
+        }
+
+        Ok(ZipFile {
+            header: ZipFileHeader {
+                compression_type,
+                crc: entry.crc,
+                uncompressed_size: entry.uncompressed_size,
+                filename: entry.filename,
+                raw_filename: entry.raw_filename,
+                host_os: HostOs::try_from_u8(entry.host_os),
+                file_comment: entry.file_comment,
+                modification_time: Some(unix_from_dos_datetime(entry.dos_time, entry.dos_date)),
+                external_file_attributes: entry.external_file_attributes,
+                internal_file_attributes: entry.internal_file_attributes,
+                extra_fields: ExtraFields::parse(&extra_field),
+                alignment: None,
+                encrypted: false,
+                data_descriptor: false,
+                stored_fallback: false,
+                compression_level: CompressionLevel::default(),
+                extra_flags: entry.general_purpose_flag,
+            },
+            data,
+        })
+    }
+}
+
+impl CentralDirEntry {
+    /// Replace the values that didn't fit into the central directory entry with the ones from the
+    /// ZIP64 extended information extra field, if there is one.
+    fn apply_zip64_extra_field(&mut self, mut extra_field: &[u8]) {
+        while extra_field.len() >= 4 {
+            let header_id = u16_at(extra_field, 0);
+            let field_size = u16_at(extra_field, 2) as usize;
+            let Some(mut field) = extra_field.get(4..4 + field_size) else {
+                return;
+            };
+            if header_id == ZIP64_EXTRA_FIELD_ID {
+                // Values are only present if the corresponding field is set to the sentinel, in
+                // this order
+                for value in [
+                    &mut self.uncompressed_size,
+                    &mut self.compressed_size,
+                    &mut self.local_header_offset,
+                ] {
+                    if *value == u32::MAX as u64 && field.len() >= 8 {
+                        *value = u64_at(field, 0);
+                        field = &field[8..];
+                    }
+                }
+                return;
+            }
+            extra_field = &extra_field[4 + field_size..];
+        }
+    }
+}
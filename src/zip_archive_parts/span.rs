@@ -0,0 +1,120 @@
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+use crate::Error;
+
+/// Written at the start of the first volume of a split archive
+const SPANNING_SIGNATURE: u32 = 0x08074B50;
+/// Replaces [`SPANNING_SIGNATURE`] if the archive fit into a single volume after all
+const SINGLE_VOLUME_MARKER: u32 = 0x30304B50;
+
+/// Smallest volume size allowed, same as Info-ZIP's
+pub const MIN_VOLUME_SIZE: u64 = 64 * 1024;
+
+/// Writer that splits the archive into volumes of at most `max_volume_size` bytes, opening each
+/// of them with `open_volume`. Only querying the stream position is supported as seeking, which
+/// returns the position in the current volume.
+pub struct SpanningWriter<W: Write, F> {
+    open_volume: F,
+    volume: BufWriter<W>,
+    disk_number: u32,
+    position: u64,
+    max_volume_size: u64,
+}
+
+impl<W: Write + Seek, F: FnMut(usize) -> std::io::Result<W>> SpanningWriter<W, F> {
+    pub fn new(max_volume_size: u64, mut open_volume: F) -> Result<Self, Error> {
+        if max_volume_size < MIN_VOLUME_SIZE {
+            return Err(Error::VolumeTooSmall);
+        }
+        let volume = BufWriter::new(open_volume(0)?);
+        let mut writer = Self {
+            open_volume,
+            volume,
+            disk_number: 0,
+            position: 0,
+            max_volume_size,
+        };
+        writer.write_all(&SPANNING_SIGNATURE.to_le_bytes())?;
+        Ok(writer)
+    }
+
+    /// Number of the current volume, starting from 0
+    #[inline]
+    pub fn disk_number(&self) -> u32 {
+        self.disk_number
+    }
+
+    /// Start a new volume if `len` bytes don't fit into the current one, so that headers are
+    /// not split between volumes. Only headers larger than a whole volume are split.
+    pub fn reserve(&mut self, len: u64) -> std::io::Result<()> {
+        if self.position > 0 && self.position + len > self.max_volume_size {
+            self.next_volume()?;
+        }
+        Ok(())
+    }
+
+    fn next_volume(&mut self) -> std::io::Result<()> {
+        self.volume.flush()?;
+        self.disk_number += 1;
+        self.position = 0;
+        self.volume = BufWriter::new((self.open_volume)(self.disk_number as usize)?);
+        Ok(())
+    }
+
+    /// Write `buf` into the current volume as a whole, even if it exceeds the volume size
+    pub fn write_unsplit(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        self.volume.write_all(buf)?;
+        self.position += buf.len() as u64;
+        Ok(())
+    }
+
+    /// Flush the last volume and return the amount of volumes. If there is only one, the spanning
+    /// signature at its start is replaced with the marker for archives that ended up not being
+    /// split, as described in section 8.5.4 of the APPNOTE.
+    pub fn finish(mut self) -> std::io::Result<usize> {
+        if self.disk_number == 0 {
+            self.volume.seek(SeekFrom::Start(0))?;
+            self.volume.write_all(&SINGLE_VOLUME_MARKER.to_le_bytes())?;
+        }
+        self.volume.flush()?;
+        Ok(self.disk_number as usize + 1)
+    }
+}
+
+impl<W: Write + Seek, F: FnMut(usize) -> std::io::Result<W>> Write for SpanningWriter<W, F> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // Data that doesn't fit continues in the next volume
+        if self.position >= self.max_volume_size && !buf.is_empty() {
+            self.next_volume()?;
+        }
+        let available = self.max_volume_size - self.position;
+        let len = buf
+            .len()
+            .min(usize::try_from(available).unwrap_or(usize::MAX));
+        let written = self.volume.write(&buf[..len])?;
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.volume.flush()
+    }
+}
+
+impl<W: Write, F> Seek for SpanningWriter<W, F> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match pos {
+            SeekFrom::Current(0) => Ok(self.position),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "seeking is not supported in split archives",
+            )),
+        }
+    }
+
+    #[inline]
+    fn stream_position(&mut self) -> std::io::Result<u64> {
+        Ok(self.position)
+    }
+}
@@ -0,0 +1,106 @@
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+/// Wrapper for writers that can't seek. Keeps track of the amount of bytes written, so that the
+/// stream position can be queried, which is the only kind of seeking needed to write an archive.
+#[derive(Debug)]
+pub struct PositionTrackingWriter<W> {
+    inner: W,
+    position: u64,
+}
+
+impl<W: Write> PositionTrackingWriter<W> {
+    #[inline]
+    pub fn new(inner: W) -> Self {
+        Self { inner, position: 0 }
+    }
+
+    /// Wrap a writer whose data will end up at `position` in the archive
+    #[inline]
+    pub fn starting_at(inner: W, position: u64) -> Self {
+        Self { inner, position }
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for PositionTrackingWriter<W> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W> Seek for PositionTrackingWriter<W> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match pos {
+            SeekFrom::Current(0) => Ok(self.position),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "seeking is not supported by the underlying writer",
+            )),
+        }
+    }
+
+    #[inline]
+    fn stream_position(&mut self) -> std::io::Result<u64> {
+        Ok(self.position)
+    }
+}
+
+/// Buffered writer that keeps track of its position, so that querying it doesn't flush the buffer
+/// like the [`Seek`] implementation of [`BufWriter`] does. Other seeks flush the buffer and seek
+/// the underlying writer.
+#[derive(Debug)]
+pub struct PositionTrackingBufWriter<W: Write + Seek> {
+    inner: BufWriter<W>,
+    position: u64,
+}
+
+impl<W: Write + Seek> PositionTrackingBufWriter<W> {
+    #[inline]
+    pub fn new(mut inner: W) -> std::io::Result<Self> {
+        let position = inner.stream_position()?;
+        Ok(Self {
+            inner: BufWriter::new(inner),
+            position,
+        })
+    }
+}
+
+impl<W: Write + Seek> Write for PositionTrackingBufWriter<W> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write + Seek> Seek for PositionTrackingBufWriter<W> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        if pos != SeekFrom::Current(0) {
+            self.position = self.inner.seek(pos)?;
+        }
+        Ok(self.position)
+    }
+
+    #[inline]
+    fn stream_position(&mut self) -> std::io::Result<u64> {
+        Ok(self.position)
+    }
+}
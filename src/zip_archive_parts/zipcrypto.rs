@@ -0,0 +1,110 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::SystemTime,
+};
+
+/// Length of the encryption header that precedes the encrypted data
+pub const ENCRYPTION_HEADER_LEN: usize = 12;
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC32_TABLE: [u32; 256] = crc32_table();
+
+#[inline]
+fn crc32_update(crc: u32, byte: u8) -> u32 {
+    CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+}
+
+/// Traditional PKWARE encryption keys, as described in section 6.1 of the specification
+struct ZipCryptoKeys {
+    key0: u32,
+    key1: u32,
+    key2: u32,
+}
+
+impl ZipCryptoKeys {
+    fn new(password: &[u8]) -> Self {
+        let mut keys = Self {
+            key0: 0x12345678,
+            key1: 0x23456789,
+            key2: 0x34567890,
+        };
+        password.iter().for_each(|&byte| keys.update(byte));
+        keys
+    }
+
+    fn update(&mut self, byte: u8) {
+        self.key0 = crc32_update(self.key0, byte);
+        self.key1 = self
+            .key1
+            .wrapping_add(self.key0 & 0xFF)
+            .wrapping_mul(134775813)
+            .wrapping_add(1);
+        self.key2 = crc32_update(self.key2, (self.key1 >> 24) as u8);
+    }
+
+    #[inline]
+    fn stream_byte(&self) -> u8 {
+        let temp = (self.key2 | 2) as u16;
+        (temp.wrapping_mul(temp ^ 1) >> 8) as u8
+    }
+
+    #[inline]
+    fn encrypt_byte(&mut self, byte: u8) -> u8 {
+        let encrypted = byte ^ self.stream_byte();
+        self.update(byte);
+        encrypted
+    }
+}
+
+/// Random bytes for the encryption header. They don't have to be cryptographically secure, the
+/// cipher is broken anyway.
+fn random_header_bytes() -> [u8; ENCRYPTION_HEADER_LEN] {
+    let state = RandomState::new();
+    let mut bytes = [0; ENCRYPTION_HEADER_LEN];
+    for (i, chunk) in bytes.chunks_mut(8).enumerate() {
+        let mut hasher = state.build_hasher();
+        hasher.write_usize(i);
+        if let Ok(since_epoch) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            hasher.write_u128(since_epoch.as_nanos());
+        }
+        chunk.copy_from_slice(&hasher.finish().to_le_bytes()[..chunk.len()]);
+    }
+    bytes
+}
+
+/// Encrypt `data` with `password`, prepending the encryption header. The last header byte is
+/// `check_byte`, which extractors use to check the password.
+pub fn encrypt(data: &[u8], password: &[u8], check_byte: u8) -> Vec<u8> {
+    let mut keys = ZipCryptoKeys::new(password);
+    let mut header = random_header_bytes();
+    header[ENCRYPTION_HEADER_LEN - 1] = check_byte;
+
+    let mut encrypted = Vec::with_capacity(ENCRYPTION_HEADER_LEN + data.len());
+    encrypted.extend(
+        header
+            .iter()
+            .chain(data)
+            .map(|&byte| keys.encrypt_byte(byte)),
+    );
+    encrypted
+}
@@ -0,0 +1,127 @@
+//! Helpers for inspecting written archives, shared by the integration tests
+#![allow(dead_code)]
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+pub const LOCAL_FILE_HEADER_SIGNATURE: u32 = 0x04034B50;
+pub const CENTRAL_FILE_HEADER_SIGNATURE: u32 = 0x02014B50;
+pub const END_OF_CENTRAL_DIR_SIGNATURE: u32 = 0x06054B50;
+pub const ZIP64_END_OF_CENTRAL_DIR_SIGNATURE: u32 = 0x06064B50;
+
+pub const LOCAL_FILE_HEADER_LEN: usize = 30;
+pub const CENTRAL_DIR_ENTRY_LEN: usize = 46;
+pub const FOOTER_LEN: usize = 22;
+
+pub fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+pub fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+pub fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// Path for a file that a test writes, in the directory cargo provides for them
+pub fn temp_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join(name)
+}
+
+/// Output of `unzip` with these arguments, or `None` if unzip is not installed
+pub fn unzip(args: &[&std::ffi::OsStr]) -> Option<String> {
+    let output = Command::new("unzip").args(args).output().ok()?;
+    assert!(
+        output.status.success(),
+        "unzip failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Some(String::from_utf8(output.stdout).unwrap())
+}
+
+/// End of central directory record of an archive without a comment
+pub fn footer(archive: &[u8]) -> &[u8] {
+    let footer = &archive[archive.len() - FOOTER_LEN..];
+    assert_eq!(u32_at(footer, 0), END_OF_CENTRAL_DIR_SIGNATURE);
+    footer
+}
+
+/// Central directory entry, with the fields the tests look at
+#[derive(Debug)]
+pub struct CentralEntry {
+    pub version_made_by: u16,
+    pub version_needed: u16,
+    pub compression_method: u16,
+    pub crc: u32,
+    pub compressed_size: u32,
+    pub uncompressed_size: u32,
+    pub external_attributes: u32,
+    pub local_header_offset: u32,
+    pub name: Vec<u8>,
+    pub extra_field: Vec<u8>,
+}
+
+/// Central directory entries of an archive that is not ZIP64 and has no comment
+pub fn central_entries(archive: &[u8]) -> Vec<CentralEntry> {
+    let footer = footer(archive);
+    let mut offset = u32_at(footer, 16) as usize;
+    (0..u16_at(footer, 10))
+        .map(|_| {
+            let header = &archive[offset..];
+            assert_eq!(u32_at(header, 0), CENTRAL_FILE_HEADER_SIGNATURE);
+            let name_len = u16_at(header, 28) as usize;
+            let extra_field_len = u16_at(header, 30) as usize;
+            let comment_len = u16_at(header, 32) as usize;
+            let name_start = CENTRAL_DIR_ENTRY_LEN;
+            let extra_field_start = name_start + name_len;
+            offset += extra_field_start + extra_field_len + comment_len;
+            CentralEntry {
+                version_made_by: u16_at(header, 4),
+                version_needed: u16_at(header, 6),
+                compression_method: u16_at(header, 10),
+                crc: u32_at(header, 16),
+                compressed_size: u32_at(header, 20),
+                uncompressed_size: u32_at(header, 24),
+                external_attributes: u32_at(header, 38),
+                local_header_offset: u32_at(header, 42),
+                name: header[name_start..extra_field_start].to_vec(),
+                extra_field: header[extra_field_start..extra_field_start + extra_field_len]
+                    .to_vec(),
+            }
+        })
+        .collect()
+}
+
+/// Extra field of the local header at `offset`
+pub fn local_extra_field(archive: &[u8], offset: usize) -> &[u8] {
+    let header = &archive[offset..];
+    assert_eq!(u32_at(header, 0), LOCAL_FILE_HEADER_SIGNATURE);
+    let start = LOCAL_FILE_HEADER_LEN + u16_at(header, 26) as usize;
+    &header[start..start + u16_at(header, 28) as usize]
+}
+
+/// The field with this header ID in an extra field, including its header
+pub fn find_extra_field(extra_field: &[u8], id: u16) -> Option<&[u8]> {
+    let mut rest = extra_field;
+    while rest.len() >= 4 {
+        let len = 4 + u16_at(rest, 2) as usize;
+        if u16_at(rest, 0) == id {
+            return Some(&rest[..len]);
+        }
+        rest = &rest[len..];
+    }
+    None
+}
+
+/// CRC-32 as used by zip, computed bit by bit
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |crc, _| {
+            (crc >> 1) ^ (0xEDB88320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
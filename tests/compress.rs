@@ -0,0 +1,169 @@
+use std::{
+    path::Path,
+    sync::{atomic::AtomicBool, Arc, Mutex},
+};
+
+use mtzip::{CompressionType, Error, ZipArchive};
+
+fn archive_with_missing_file() -> ZipArchive<'static, 'static, 'static> {
+    let mut zipper = ZipArchive::new();
+    zipper.set_preserve_order(true);
+    for i in 0..8 {
+        zipper
+            .add_file_from_memory(vec![b'a'; 1000], format!("{i}.txt"))
+            .done();
+    }
+    zipper
+        .add_file_from_fs(Path::new("this/file/does/not/exist.txt"), "missing.txt")
+        .done();
+    for i in 8..16 {
+        zipper
+            .add_file_from_memory(vec![b'a'; 1000], format!("{i}.txt"))
+            .done();
+    }
+    zipper
+}
+
+#[test]
+fn cancellable_compression_keeps_compressed_files_on_error() {
+    let mut zipper = archive_with_missing_file();
+    let cancel = AtomicBool::new(false);
+    let result = zipper.compress_with_cancellation(1, &cancel);
+    assert!(matches!(result, Err(Error::OpenInput { .. })), "{result:?}");
+
+    // Files before the missing one are compressed, the ones after it are either compressed or
+    // still queued, only the missing one is gone
+    let compressed: Vec<_> = zipper
+        .entries()
+        .map(|entry| entry.name.to_owned())
+        .collect();
+    assert!(compressed.len() >= 8);
+    assert_eq!(
+        compressed[..8],
+        (0..8).map(|i| format!("{i}.txt")).collect::<Vec<_>>()
+    );
+    assert_eq!(compressed.len() + zipper.queued_jobs(), 16);
+
+    // Same as compressing without cancellation
+    let mut zipper = archive_with_missing_file();
+    assert!(zipper.compress_with_threads(1).is_err());
+    assert_eq!(zipper.entries().count() + zipper.queued_jobs(), 16);
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn rayon_compression_keeps_compressed_files_on_error() {
+    // With a single thread the jobs are compressed in order
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .unwrap();
+
+    let mut zipper = archive_with_missing_file();
+    let result = zipper.compress_with_rayon_in_pool(&pool);
+    assert!(matches!(result, Err(Error::OpenInput { .. })), "{result:?}");
+    let compressed: Vec<_> = zipper
+        .entries()
+        .map(|entry| entry.name.to_owned())
+        .collect();
+    assert_eq!(
+        compressed,
+        (0..8).map(|i| format!("{i}.txt")).collect::<Vec<_>>()
+    );
+    assert_eq!(zipper.queued_jobs(), 8);
+
+    // The remaining jobs can be compressed once the missing file is out of the way
+    zipper.compress_with_rayon_in_pool(&pool).unwrap();
+    assert_eq!(zipper.entries().count(), 16);
+
+    // Files that were written are gone with the broken output, the others stay queued
+    let mut zipper = archive_with_missing_file();
+    let mut output = std::io::Cursor::new(Vec::new());
+    assert!(zipper.write_with_rayon_in_pool(&mut output, &pool).is_err());
+    assert_eq!(zipper.queued_jobs(), 8);
+}
+
+#[test]
+fn empty_file_is_not_reported_as_fallback() {
+    let mut zipper = ZipArchive::new();
+    zipper.set_fallback_to_stored(true);
+    zipper.add_file_from_memory(Vec::new(), "empty.txt").done();
+    zipper.add_file_from_memory(b"x".as_slice(), "x.txt").done();
+
+    let mut report = zipper.compress_report(1).unwrap();
+    report.sort_by(|a, b| a.name.cmp(&b.name));
+    assert_eq!(report[0].name, "empty.txt");
+    assert_eq!(report[0].compression, CompressionType::Stored);
+    assert!(!report[0].fell_back_to_stored);
+    assert_eq!(report[1].compression, CompressionType::Stored);
+    assert!(report[1].fell_back_to_stored);
+}
+
+#[test]
+fn compression_selector_runs_when_compressing() {
+    let mut zipper = ZipArchive::new();
+    let threads = Arc::new(Mutex::new(Vec::new()));
+    let selector_threads = Arc::clone(&threads);
+    zipper.set_compression_selector(move |path| {
+        let thread = std::thread::current().name().map(str::to_owned);
+        selector_threads.lock().unwrap().push(thread);
+        if path.ends_with(".png") {
+            CompressionType::Stored
+        } else {
+            CompressionType::Deflate
+        }
+    });
+    zipper
+        .add_file_from_memory(vec![b'a'; 1000], "image.png")
+        .done();
+    zipper
+        .add_file_from_memory(vec![b'a'; 1000], "text.txt")
+        .done();
+    zipper
+        .add_file_from_memory(vec![b'a'; 1000], "forced.png")
+        .compression_type(CompressionType::Deflate)
+        .done();
+    assert!(threads.lock().unwrap().is_empty());
+
+    let mut report = zipper.compress_report(2).unwrap();
+    report.sort_by(|a, b| a.name.cmp(&b.name));
+    let compression: Vec<_> = report.iter().map(|entry| entry.compression).collect();
+    assert_eq!(
+        compression,
+        [
+            CompressionType::Deflate,
+            CompressionType::Stored,
+            CompressionType::Deflate
+        ]
+    );
+    // Only the files left to the selector are passed to it, by the workers
+    let threads = threads.lock().unwrap();
+    assert_eq!(threads.len(), 2);
+    assert!(threads
+        .iter()
+        .all(|thread| thread.as_deref().unwrap().starts_with("mtzip-worker-")));
+}
+
+#[test]
+fn default_compression_type_without_selector() {
+    let mut zipper = ZipArchive::new();
+    zipper
+        .add_file_from_memory(vec![b'a'; 1000], "a.txt")
+        .compression_type(CompressionType::Default)
+        .done();
+    let report = zipper.compress_report(1).unwrap();
+    assert_eq!(report[0].compression, CompressionType::Deflate);
+
+    let mut zipper = ZipArchive::new();
+    zipper
+        .add_precompressed(vec![b'a'], 0, 1, CompressionType::Default, "a.txt")
+        .done();
+    let result = zipper.compress();
+    assert!(
+        matches!(
+            result,
+            Err(Error::UnsupportedCompression(CompressionType::Default))
+        ),
+        "{result:?}"
+    );
+}
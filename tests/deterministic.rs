@@ -0,0 +1,66 @@
+mod common;
+
+use std::{
+    fs::File,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use common::{central_entries, temp_path};
+use mtzip::ZipArchive;
+
+fn build(input_dir: &Path) -> Vec<u8> {
+    let mut zipper = ZipArchive::new();
+    zipper.set_deterministic(true);
+    // Added in reverse, so that the output is only sorted if the archive sorts it
+    for i in (0..32).rev() {
+        zipper
+            .add_file_from_fs(input_dir.join(format!("{i}.txt")), format!("fs/{i}.txt"))
+            .done();
+        zipper
+            .add_file_from_memory(vec![b'a' + i as u8; 1000 * i], format!("memory/{i}.txt"))
+            .done();
+    }
+    zipper.add_directory("empty").done();
+    zipper.compress_with_threads(4).unwrap();
+    zipper.write_to_vec().unwrap()
+}
+
+fn set_modification_times(input_dir: &Path, time: SystemTime) {
+    for i in 0..32 {
+        File::options()
+            .write(true)
+            .open(input_dir.join(format!("{i}.txt")))
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+    }
+}
+
+#[test]
+fn deterministic_output_is_byte_identical() {
+    let input_dir = temp_path("deterministic_output_is_byte_identical");
+    std::fs::create_dir_all(&input_dir).unwrap();
+    for i in 0..32 {
+        std::fs::write(input_dir.join(format!("{i}.txt")), i.to_string().repeat(i)).unwrap();
+    }
+
+    set_modification_times(
+        &input_dir,
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000),
+    );
+    let first = build(&input_dir);
+    set_modification_times(&input_dir, SystemTime::now());
+    let second = build(&input_dir);
+    assert!(first == second, "archives differ");
+
+    let names: Vec<_> = central_entries(&first)
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+    let mut sorted = names.clone();
+    sorted.sort();
+    assert_eq!(names, sorted);
+
+    std::fs::remove_dir_all(input_dir).unwrap();
+}
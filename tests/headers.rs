@@ -0,0 +1,240 @@
+mod common;
+
+use std::{
+    process::Command,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use common::{
+    central_entries, find_extra_field, footer, local_extra_field, u16_at, LOCAL_FILE_HEADER_LEN,
+};
+use mtzip::{CompressionType, Error, ExtraField, FileOptions, HostOs, ZipArchive};
+
+const UNIX_EXTENDED_TIMESTAMP_ID: u16 = 0x5455;
+
+#[test]
+fn end_of_central_directory_counts_entries() {
+    let mut zipper = ZipArchive::new();
+    zipper
+        .add_file_from_memory(b"first".as_slice(), "first.txt")
+        .done();
+    zipper
+        .add_file_from_memory(b"second".as_slice(), "second.txt")
+        .done();
+    let archive = zipper.write_to_vec().unwrap();
+
+    let footer = footer(&archive);
+    // Entries on this disk
+    assert_eq!(u16_at(footer, 8), 2);
+    // Total entries
+    assert_eq!(u16_at(footer, 10), 2);
+    assert_eq!(central_entries(&archive).len(), 2);
+}
+
+#[test]
+fn symlink_has_unix_mode_in_central_header() {
+    let mut zipper = ZipArchive::new();
+    zipper.set_host_os(HostOs::Unix);
+    zipper.add_symlink("latest", "releases/1.0.0").done();
+    let archive = zipper.write_to_vec().unwrap();
+
+    let entries = central_entries(&archive);
+    let entry = &entries[0];
+    assert_eq!(entry.version_made_by >> 8, HostOs::Unix as u16);
+    // S_IFLNK with all permissions, in the high 16 bits
+    assert_eq!(entry.external_attributes >> 16, 0o120777);
+    // Stored, with the link target as data
+    assert_eq!(entry.compression_method, 0);
+    let data_start = LOCAL_FILE_HEADER_LEN + entry.name.len();
+    assert_eq!(
+        &archive[data_start..data_start + "releases/1.0.0".len()],
+        b"releases/1.0.0"
+    );
+
+    #[cfg(unix)]
+    {
+        let path = common::temp_path("symlink_has_unix_mode_in_central_header.zip");
+        let output_dir = common::temp_path("symlink_has_unix_mode_in_central_header");
+        let _ = std::fs::remove_dir_all(&output_dir);
+        std::fs::write(&path, &archive).unwrap();
+        if common::unzip(&[
+            "-q".as_ref(),
+            path.as_ref(),
+            "-d".as_ref(),
+            output_dir.as_ref(),
+        ])
+        .is_some()
+        {
+            let link = output_dir.join("latest");
+            assert!(link.symlink_metadata().unwrap().is_symlink());
+            assert_eq!(
+                std::fs::read_link(link).unwrap(),
+                std::path::Path::new("releases/1.0.0")
+            );
+            std::fs::remove_dir_all(output_dir).unwrap();
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+/// Extended timestamp field of the first entry, from the local header and the central header
+fn timestamp_fields(archive: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let entry = &central_entries(archive)[0];
+    let local = local_extra_field(archive, entry.local_header_offset as usize);
+    (
+        find_extra_field(local, UNIX_EXTENDED_TIMESTAMP_ID)
+            .unwrap()
+            .to_vec(),
+        find_extra_field(&entry.extra_field, UNIX_EXTENDED_TIMESTAMP_ID)
+            .unwrap()
+            .to_vec(),
+    )
+}
+
+#[test]
+fn extended_timestamp_matches_info_zip_layout() {
+    const MOD_TIME: i32 = 1700000000;
+    const AC_TIME: i32 = 1600000000;
+
+    let mut zipper = ZipArchive::new();
+    zipper
+        .add_file_from_memory(b"hi\n".as_slice(), "f.txt")
+        .extra_field(ExtraField::UnixExtendedTimestamp {
+            mod_time: Some(MOD_TIME),
+            ac_time: Some(AC_TIME),
+            cr_time: None,
+        })
+        .done();
+    let (local, central) = timestamp_fields(&zipper.write_to_vec().unwrap());
+
+    // As written by Info-ZIP Zip 3.0 for a file with these modification and access times. Both
+    // headers have the same flags, but the central header only has the modification time.
+    assert_eq!(
+        local,
+        [0x55, 0x54, 0x09, 0x00, 0x03, 0x00, 0xF1, 0x53, 0x65, 0x00, 0x10, 0x5E, 0x5F]
+    );
+    assert_eq!(
+        central,
+        [0x55, 0x54, 0x05, 0x00, 0x03, 0x00, 0xF1, 0x53, 0x65]
+    );
+
+    // Compare with the installed Info-ZIP too, if there is one
+    let dir = common::temp_path("extended_timestamp_matches_info_zip_layout");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("f.txt");
+    std::fs::write(&input, b"hi\n").unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(&input)
+        .unwrap()
+        .set_times(
+            std::fs::FileTimes::new()
+                .set_modified(UNIX_EPOCH + Duration::from_secs(MOD_TIME as u64))
+                .set_accessed(UNIX_EPOCH + Duration::from_secs(AC_TIME as u64)),
+        )
+        .unwrap();
+    let zipped = Command::new("zip")
+        .current_dir(&dir)
+        .args(["-q", "reference.zip", "f.txt"])
+        .status();
+    if zipped.is_ok_and(|status| status.success()) {
+        let reference = std::fs::read(dir.join("reference.zip")).unwrap();
+        assert_eq!(timestamp_fields(&reference), (local, central));
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+fn raw_field(len: usize) -> ExtraField {
+    ExtraField::Raw {
+        header_id: 0xCAFE,
+        data: vec![0; len],
+    }
+}
+
+fn assert_extra_field_too_long(mut zipper: ZipArchive) {
+    match zipper.write_to_vec() {
+        Err(Error::ExtraFieldTooLong(path)) => assert_eq!(path, "file.txt"),
+        result => panic!(
+            "unexpected result: {:?}",
+            result.map(|archive| archive.len())
+        ),
+    }
+}
+
+#[test]
+fn extra_field_too_long() {
+    let mut zipper = ZipArchive::new();
+    zipper
+        .add_file_from_memory(b"data".as_slice(), "file.txt")
+        .extra_field(raw_field(70000))
+        .done();
+    assert_extra_field_too_long(zipper);
+}
+
+#[test]
+fn extra_fields_too_long_in_total() {
+    let mut zipper = ZipArchive::new();
+    zipper
+        .add_file_from_memory(b"data".as_slice(), "file.txt")
+        .extra_fields([raw_field(40000), raw_field(40000)])
+        .done();
+    assert_extra_field_too_long(zipper);
+}
+
+#[test]
+fn extra_fields_too_long_with_alignment() {
+    let mut zipper = ZipArchive::new();
+    zipper
+        .add_file_from_memory(b"data".as_slice(), "file.txt")
+        .compression_type(CompressionType::Stored)
+        .extra_field(raw_field(65500))
+        .alignment(32768)
+        .done();
+    assert_extra_field_too_long(zipper);
+
+    // Fits without the alignment
+    let mut zipper = ZipArchive::new();
+    zipper
+        .add_file_from_memory(b"data".as_slice(), "file.txt")
+        .extra_field(raw_field(65500))
+        .done();
+    assert!(zipper.write_to_vec().is_ok());
+}
+
+#[test]
+fn directory_path_ends_with_slash() {
+    let mut zipper = ZipArchive::new();
+    zipper.add_directory("with_slash/").done();
+    zipper.add_directory("without_slash").done();
+    zipper.add_directory("windows\\style\\").done();
+    let archive = zipper.write_to_vec().unwrap();
+
+    let names: Vec<_> = central_entries(&archive)
+        .into_iter()
+        .map(|entry| String::from_utf8(entry.name).unwrap())
+        .collect();
+    assert_eq!(names, ["with_slash/", "without_slash/", "windows/style/"]);
+}
+
+#[test]
+fn file_options_replace_dos_attributes() {
+    let mut zipper = ZipArchive::new();
+    zipper.set_preserve_order(true);
+    zipper
+        .add_file_from_memory(b"hidden".as_slice(), "builder.txt")
+        .dos_attributes(0x02)
+        .done();
+    zipper
+        .add_file_from_memory(b"read-only".as_slice(), "options.txt")
+        .dos_attributes(0x02)
+        .options(&FileOptions::new().dos_attributes(0x01))
+        .done();
+    let archive = zipper.write_to_vec().unwrap();
+
+    let dos_attributes: Vec<_> = central_entries(&archive)
+        .iter()
+        .map(|entry| entry.external_attributes & 0xFFFF)
+        .collect();
+    assert_eq!(dos_attributes, [0x02, 0x01]);
+}
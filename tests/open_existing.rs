@@ -0,0 +1,98 @@
+mod common;
+
+use std::io::Cursor;
+
+use common::{central_entries, footer, temp_path, u32_at, unzip};
+use mtzip::{CompressionType, HostOs, ZipArchive};
+
+#[test]
+fn host_os_is_preserved() {
+    let mut zipper = ZipArchive::new();
+    zipper.set_host_os(HostOs::Windows);
+    zipper
+        .add_file_from_memory(b"Hello, world!".as_slice(), "hello.txt")
+        .done();
+    let archive = zipper.write_to_vec().unwrap();
+
+    let mut zipper = ZipArchive::open_existing(Cursor::new(archive)).unwrap();
+    zipper.set_host_os(HostOs::Unix);
+    zipper
+        .add_file_from_memory(b"added".as_slice(), "added.txt")
+        .done();
+    let archive = zipper.write_to_vec().unwrap();
+
+    let hosts: Vec<_> = central_entries(&archive)
+        .iter()
+        .map(|entry| entry.version_made_by >> 8)
+        .collect();
+    assert_eq!(hosts, [HostOs::Windows as u16, HostOs::Unix as u16]);
+}
+
+#[test]
+fn multi_disk_archive_is_rejected() {
+    let dir = temp_path("multi_disk_archive_is_rejected");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // Doesn't compress, so that it doesn't fit into a single volume
+    let data: Vec<u8> = (0..100_000u32)
+        .map(|i| (i.wrapping_mul(2654435761) >> 24) as u8)
+        .collect();
+    let mut zipper = ZipArchive::new();
+    zipper
+        .add_file_from_memory(data, "data.bin")
+        .compression_type(CompressionType::Stored)
+        .done();
+    let volumes = zipper
+        .write_split(64 * 1024, |i| {
+            std::fs::File::create(dir.join(format!("{i}")))
+        })
+        .unwrap();
+    assert!(volumes > 1);
+    let first_volume = std::fs::read(dir.join("0")).unwrap();
+    assert_eq!(u32_at(&first_volume, 0), 0x08074B50);
+
+    let last_volume = std::fs::read(dir.join(format!("{}", volumes - 1))).unwrap();
+    let error = ZipArchive::open_existing(Cursor::new(last_volume)).unwrap_err();
+    assert!(error.to_string().contains("multi-disk"), "{error}");
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn single_volume_split_archive_is_marked() {
+    let path = temp_path("single_volume_split_archive_is_marked.zip");
+    let mut zipper = ZipArchive::new();
+    zipper
+        .add_file_from_memory(b"Hello, world!".as_slice(), "hello.txt")
+        .done();
+    let volumes = zipper
+        .write_split(64 * 1024, |_| std::fs::File::create(&path))
+        .unwrap();
+    assert_eq!(volumes, 1);
+
+    let archive = std::fs::read(&path).unwrap();
+    // PK00 instead of the spanning signature
+    assert_eq!(u32_at(&archive, 0), 0x30304B50);
+    if let Some(listing) = unzip(&["-l".as_ref(), path.as_ref()]) {
+        assert!(listing.contains("hello.txt"), "{listing}");
+    }
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn truncated_entry_is_rejected() {
+    let mut zipper = ZipArchive::new();
+    zipper
+        .add_file_from_memory(b"Hello, world!".as_slice(), "hello.txt")
+        .compression_type(CompressionType::Stored)
+        .done();
+    let mut archive = zipper.write_to_vec().unwrap();
+
+    // Compressed size in the central directory entry claims far more data than the archive has
+    let central_dir_offset = common::u32_at(footer(&archive), 16) as usize;
+    archive[central_dir_offset + 20..central_dir_offset + 24]
+        .copy_from_slice(&0xFFFF_0000u32.to_le_bytes());
+    let error = ZipArchive::open_existing(Cursor::new(archive)).unwrap_err();
+    assert!(error.to_string().contains("truncated"), "{error}");
+}
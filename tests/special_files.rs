@@ -0,0 +1,65 @@
+//! Files whose reported size doesn't match their contents
+#![cfg(unix)]
+
+mod common;
+
+use std::{io::Write, process::Command, thread};
+
+use common::{central_entries, crc32, temp_path, unzip};
+use mtzip::{CompressionType, ZipArchive};
+
+/// Archive the contents written into a named pipe, which reports a size of 0
+fn archive_fifo(name: &str, compression_type: CompressionType) {
+    let dir = temp_path(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let fifo = dir.join("fifo");
+    match Command::new("mkfifo").arg(&fifo).status() {
+        Ok(status) if status.success() => {}
+        // No way to make one
+        _ => return,
+    }
+    assert_eq!(std::fs::metadata(&fifo).unwrap().len(), 0);
+
+    let data: Vec<u8> = (0..100_000u32).flat_map(u32::to_le_bytes).collect();
+    let writer = {
+        let fifo = fifo.clone();
+        let data = data.clone();
+        // Opening blocks until the archive opens the other end
+        thread::spawn(move || std::fs::File::create(fifo)?.write_all(&data))
+    };
+
+    let mut zipper = ZipArchive::new();
+    zipper
+        .add_file_from_fs(fifo.as_path(), "fifo.bin")
+        .compression_type(compression_type)
+        .done();
+    let archive_path = dir.join("archive.zip");
+    zipper.write_to_path(&archive_path).unwrap();
+    writer.join().unwrap().unwrap();
+
+    let archive = std::fs::read(&archive_path).unwrap();
+    let entry = &central_entries(&archive)[0];
+    assert_eq!(entry.compression_method, compression_type as u16);
+    assert_eq!(entry.uncompressed_size as usize, data.len());
+    assert_eq!(entry.crc, crc32(&data));
+    if compression_type == CompressionType::Stored {
+        assert_eq!(entry.compressed_size as usize, data.len());
+    }
+    // Checks the data against the crc
+    unzip(&["-t".as_ref(), archive_path.as_ref()]);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+/// Stored files from the filesystem are streamed into the archive
+#[test]
+fn fifo_stored() {
+    archive_fifo("fifo_stored", CompressionType::Stored);
+}
+
+/// Compressed files are compressed into memory first
+#[test]
+fn fifo_deflated() {
+    archive_fifo("fifo_deflated", CompressionType::Deflate);
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    io::{Read, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use common::u32_at;
+use mtzip::{level::CompressionLevel, ZipArchive};
+
+/// Allocator that keeps track of the largest amount of memory allocated at once
+struct PeakAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(allocated, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: PeakAllocator = PeakAllocator;
+
+/// Writer that only remembers the last bytes written to it
+struct Tail {
+    len: u64,
+    tail: Vec<u8>,
+}
+
+impl Write for Tail {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.len += buf.len() as u64;
+        self.tail.extend_from_slice(buf);
+        let excess = self.tail.len().saturating_sub(1024);
+        self.tail.drain(..excess);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn streamed_reader_uses_bounded_memory() {
+    const LEN: u64 = 1 << 30;
+    const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074B50;
+
+    let mut zipper = ZipArchive::new();
+    zipper
+        .add_file_from_reader(std::io::repeat(0).take(LEN), "zeros.bin")
+        .compression_level(CompressionLevel::fast())
+        .streamed()
+        .done();
+    let mut output = Tail {
+        len: 0,
+        tail: Vec::with_capacity(2048),
+    };
+
+    let baseline = ALLOCATED.load(Ordering::Relaxed);
+    PEAK.store(baseline, Ordering::Relaxed);
+    zipper.write_stream(&mut output).unwrap();
+    let peak = PEAK.load(Ordering::Relaxed) - baseline;
+    assert!(peak < 16 * 1024 * 1024, "{peak} bytes allocated at once");
+
+    // Data descriptor of the only entry, right before the central directory
+    let central_dir_offset = u32_at(&output.tail, output.tail.len() - 22 + 16) as u64;
+    let central_dir_size = output.len - 22 - central_dir_offset;
+    let descriptor = &output.tail[output.tail.len() - 22 - central_dir_size as usize - 16..];
+    assert_eq!(u32_at(descriptor, 0), DATA_DESCRIPTOR_SIGNATURE);
+    assert_eq!(u32_at(descriptor, 12) as u64, LEN);
+}
@@ -0,0 +1,84 @@
+use std::{
+    io::{Cursor, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use flate2::write::DeflateEncoder;
+use mtzip::{level::CompressionLevel, CompressionType, Error, ZipArchive};
+
+/// Writer that counts how often it's written to and seeked
+#[derive(Default)]
+struct CountingWriter {
+    inner: Cursor<Vec<u8>>,
+    writes: usize,
+    seeks: usize,
+}
+
+impl Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writes += 1;
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for CountingWriter {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.seeks += 1;
+        self.inner.seek(pos)
+    }
+}
+
+#[test]
+fn small_entries_are_written_in_few_pieces() {
+    let mut zipper = ZipArchive::new();
+    for i in 0..1000 {
+        zipper
+            .add_file_from_memory(i.to_string().into_bytes(), format!("{i}.txt"))
+            .compression_type(CompressionType::Stored)
+            .done();
+    }
+    let mut output = CountingWriter::default();
+    let written = zipper.write(&mut output).unwrap();
+
+    assert_eq!(written, output.inner.get_ref().len() as u64);
+    // Buffered together instead of flushed for every entry
+    assert!(output.writes < 20, "{} writes", output.writes);
+    assert!(output.seeks < 5, "{} seeks", output.seeks);
+}
+
+#[test]
+fn gzip_compresses_stored_file_with_its_level() {
+    let data: Vec<u8> = (0..64 * 1024u32)
+        .map(|i| b"abcdefgh"[(i.wrapping_mul(2654435761) >> 29) as usize])
+        .collect();
+    let mut zipper = ZipArchive::new();
+    zipper
+        .add_file_from_memory(data.as_slice(), "data.bin")
+        .compression_type(CompressionType::Stored)
+        .compression_level(CompressionLevel::fast())
+        .done();
+    zipper.compress_with_threads(1).unwrap();
+    let mut output = Vec::new();
+    zipper.write_gzip(&mut output).unwrap();
+
+    let mut encoder = DeflateEncoder::new(Vec::new(), CompressionLevel::fast().into());
+    encoder.write_all(&data).unwrap();
+    let expected = encoder.finish().unwrap();
+    // Deflate stream between the 10 byte header and the 8 byte trailer
+    assert_eq!(output[10..output.len() - 8], expected);
+}
+
+#[test]
+fn gzip_keeps_job_of_missing_file() {
+    let mut zipper = ZipArchive::new();
+    zipper
+        .add_file_from_fs(Path::new("this/file/does/not/exist.txt"), "missing.txt")
+        .done();
+    let result = zipper.write_gzip(Vec::new());
+    assert!(matches!(result, Err(Error::OpenInput { .. })), "{result:?}");
+    assert_eq!(zipper.queued_jobs(), 1);
+}
@@ -0,0 +1,134 @@
+mod common;
+
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+use common::{
+    footer, temp_path, u16_at, u32_at, u64_at, unzip, LOCAL_FILE_HEADER_SIGNATURE,
+    ZIP64_END_OF_CENTRAL_DIR_SIGNATURE,
+};
+use mtzip::{CompressionType, ZipArchive};
+
+const ZIP64_EXTRA_FIELD_ID: u16 = 0x0001;
+
+/// Zeros of a fixed length, without allocating them
+struct Zeros {
+    len: u64,
+    position: u64,
+}
+
+impl Read for Zeros {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = buf.len().min((self.len - self.position) as usize);
+        buf[..n].fill(0);
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for Zeros {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.position = match pos {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset).unwrap(),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset).unwrap(),
+        };
+        Ok(self.position)
+    }
+}
+
+#[test]
+fn many_entries_round_trip() {
+    const ENTRIES: usize = 70000;
+
+    let mut zipper = ZipArchive::new();
+    zipper.set_preserve_order(true);
+    for i in 0..ENTRIES {
+        zipper
+            .add_file_from_memory(i.to_string().into_bytes(), format!("{i}.txt"))
+            .compression_type(CompressionType::Stored)
+            .done();
+    }
+    let archive = zipper.write_to_vec().unwrap();
+
+    // Entry count doesn't fit into the end of central directory record
+    assert_eq!(u16_at(footer(&archive), 10), u16::MAX);
+    let zip64_footer = &archive[archive.len() - 22 - 20 - 56..];
+    assert_eq!(u32_at(zip64_footer, 0), ZIP64_END_OF_CENTRAL_DIR_SIGNATURE);
+    assert_eq!(u64_at(zip64_footer, 32), ENTRIES as u64);
+
+    let reopened = ZipArchive::open_existing(Cursor::new(&archive)).unwrap();
+    assert_eq!(reopened.entry_count(), ENTRIES);
+    assert!(reopened
+        .entries()
+        .enumerate()
+        .all(|(i, entry)| entry.name == format!("{i}.txt")));
+
+    let path = temp_path("many_entries_round_trip.zip");
+    std::fs::write(&path, &archive).unwrap();
+    if let Some(listing) = unzip(&["-l".as_ref(), path.as_ref()]) {
+        let summary = listing.lines().last().unwrap();
+        assert!(
+            summary.ends_with(&format!("{ENTRIES} files")),
+            "unexpected summary: {summary}"
+        );
+    }
+    std::fs::remove_file(path).unwrap();
+}
+
+/// Writes an entry of more than 4 GiB, takes a while without optimizations
+#[test]
+#[ignore]
+fn large_entry_round_trip() {
+    const LEN: u64 = (u32::MAX as u64) + 4096;
+
+    let mut zipper = ZipArchive::new();
+    zipper
+        .add_file_from_seekable_reader(
+            Zeros {
+                len: LEN,
+                position: 0,
+            },
+            "zeros.bin",
+        )
+        .unwrap()
+        .streamed()
+        .done();
+    let path = temp_path("large_entry_round_trip.zip");
+    zipper.write_to_path(&path).unwrap();
+
+    let archive = std::fs::read(&path).unwrap();
+    assert_eq!(u32_at(&archive, 0), LOCAL_FILE_HEADER_SIGNATURE);
+    // version needed to extract
+    assert_eq!(u16_at(&archive, 4), 45);
+    // Both sizes are in the ZIP64 extra field, which follows the file name
+    assert_eq!(u32_at(&archive, 18), u32::MAX);
+    assert_eq!(u32_at(&archive, 22), u32::MAX);
+    let extra_field = 30 + u16_at(&archive, 26) as usize;
+    assert_eq!(u16_at(&archive, extra_field), ZIP64_EXTRA_FIELD_ID);
+    assert_eq!(u64_at(&archive, extra_field + 4), LEN);
+
+    if let Some(listing) = unzip(&["-l".as_ref(), path.as_ref()]) {
+        assert!(listing.contains(&format!("{LEN}")), "{listing}");
+    }
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn precompressed_entry_over_4_gib() {
+    const LEN: u64 = 5 << 30;
+
+    let mut zipper = ZipArchive::new();
+    zipper
+        .add_precompressed(vec![0; 16], 0, LEN, CompressionType::Deflate, "large.bin")
+        .done();
+    let mut archive = Cursor::new(Vec::new());
+    zipper.write(&mut archive).unwrap();
+    let archive = archive.into_inner();
+
+    // The local header then has both sizes in the ZIP64 extra field, the uncompressed one first
+    assert_eq!(u32_at(&archive, 18), u32::MAX);
+    assert_eq!(u32_at(&archive, 22), u32::MAX);
+    let extra_field = 30 + u16_at(&archive, 26) as usize;
+    assert_eq!(u16_at(&archive, extra_field), ZIP64_EXTRA_FIELD_ID);
+    assert_eq!(u64_at(&archive, extra_field + 4), LEN);
+}